@@ -0,0 +1,149 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::value::Value;
+
+enum Operands {
+    Int(i64, i64),
+    BigInt(BigInt, BigInt),
+    Float(f64, f64),
+}
+
+fn operands(operator: &str, left: &Value, right: &Value) -> Result<Operands, String> {
+    match (left, right) {
+        (Value::Int(left), Value::Int(right)) => Ok(Operands::Int(*left, *right)),
+        (Value::Number(left), Value::Number(right)) => Ok(Operands::Float(*left, *right)),
+        (Value::Number(left), right) if right.is_integer() => {
+            Ok(Operands::Float(*left, float(right)))
+        }
+        (left, Value::Number(right)) if left.is_integer() => {
+            Ok(Operands::Float(float(left), *right))
+        }
+        (left, right) if left.is_integer() && right.is_integer() => {
+            Ok(Operands::BigInt(big(left), big(right)))
+        }
+        (left, right) => Err(format!(
+            "Operands of {} must be numbers, got {} and {}",
+            operator,
+            left.type_name(),
+            right.type_name()
+        )),
+    }
+}
+
+fn float(value: &Value) -> f64 {
+    match value {
+        Value::Int(value) => *value as f64,
+        Value::BigInt(value) => value.to_f64().unwrap_or(f64::NAN),
+        _ => unreachable!(),
+    }
+}
+
+fn big(value: &Value) -> BigInt {
+    match value {
+        Value::Int(value) => BigInt::from(*value),
+        Value::BigInt(value) => value.as_ref().clone(),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn add(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("+", left, right)? {
+        Operands::Int(left, right) => Ok(left
+            .checked_add(right)
+            .map(Value::Int)
+            .unwrap_or_else(|| Value::from(BigInt::from(left) + right))),
+        Operands::BigInt(left, right) => Ok(Value::from(left + right)),
+        Operands::Float(left, right) => Ok(Value::Number(left + right)),
+    }
+}
+
+pub(crate) fn subtract(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("-", left, right)? {
+        Operands::Int(left, right) => Ok(left
+            .checked_sub(right)
+            .map(Value::Int)
+            .unwrap_or_else(|| Value::from(BigInt::from(left) - right))),
+        Operands::BigInt(left, right) => Ok(Value::from(left - right)),
+        Operands::Float(left, right) => Ok(Value::Number(left - right)),
+    }
+}
+
+pub(crate) fn multiply(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("*", left, right)? {
+        Operands::Int(left, right) => Ok(left
+            .checked_mul(right)
+            .map(Value::Int)
+            .unwrap_or_else(|| Value::from(BigInt::from(left) * right))),
+        Operands::BigInt(left, right) => Ok(Value::from(left * right)),
+        Operands::Float(left, right) => Ok(Value::Number(left * right)),
+    }
+}
+
+pub(crate) fn divide(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("/", left, right)? {
+        Operands::Int(left, right) => Ok(Value::Number(left as f64 / right as f64)),
+        Operands::BigInt(left, right) => Ok(Value::Number(
+            left.to_f64().unwrap_or(f64::NAN) / right.to_f64().unwrap_or(f64::NAN),
+        )),
+        Operands::Float(left, right) => Ok(Value::Number(left / right)),
+    }
+}
+
+pub(crate) fn floor_divide(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("//", left, right)? {
+        Operands::Int(_, 0) => Err("Integer division by zero".to_string()),
+        Operands::Int(left, right) => match left.checked_div(right) {
+            Some(quotient) if left % right != 0 && (left < 0) != (right < 0) => {
+                Ok(Value::Int(quotient - 1))
+            }
+            Some(quotient) => Ok(Value::Int(quotient)),
+            None => Ok(Value::from(-BigInt::from(left))),
+        },
+        Operands::BigInt(_, right) if right.is_zero() => {
+            Err("Integer division by zero".to_string())
+        }
+        Operands::BigInt(left, right) => Ok(Value::from(left.div_floor(&right))),
+        Operands::Float(left, right) => Ok(Value::Number((left / right).floor())),
+    }
+}
+
+pub(crate) fn remainder(left: &Value, right: &Value) -> Result<Value, String> {
+    match operands("%", left, right)? {
+        Operands::Int(_, 0) => Err("Integer division by zero".to_string()),
+        Operands::Int(left, right) => {
+            let remainder = left.wrapping_rem(right);
+            match remainder != 0 && (remainder < 0) != (right < 0) {
+                true => Ok(Value::Int(remainder + right)),
+                false => Ok(Value::Int(remainder)),
+            }
+        }
+        Operands::BigInt(_, right) if right.is_zero() => {
+            Err("Integer division by zero".to_string())
+        }
+        Operands::BigInt(left, right) => Ok(Value::from(left.mod_floor(&right))),
+        Operands::Float(left, right) => {
+            let remainder = left % right;
+            match remainder != 0.0 && (remainder < 0.0) != (right < 0.0) {
+                true => Ok(Value::Number(remainder + right)),
+                false => Ok(Value::Number(remainder)),
+            }
+        }
+    }
+}
+
+pub(crate) fn negate(value: &Value) -> Result<Value, String> {
+    match value {
+        Value::Int(value) => Ok(value
+            .checked_neg()
+            .map(Value::Int)
+            .unwrap_or_else(|| Value::from(-BigInt::from(*value)))),
+        Value::BigInt(value) => Ok(Value::from(-value.as_ref())),
+        Value::Number(value) => Ok(Value::Number(-value)),
+        value => Err(format!(
+            "Operand of - must be a number, got {}",
+            value.type_name()
+        )),
+    }
+}
@@ -0,0 +1,163 @@
+use num_bigint::BigInt;
+
+use crate::token::Span;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Stmt {
+    Let {
+        name: String,
+        span: Span,
+        initializer: Option<Expr>,
+    },
+    Fun(FunDecl),
+    Class(ClassDecl),
+    Return {
+        span: Span,
+        value: Option<Expr>,
+    },
+    Expression(Expr),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    For {
+        span: Span,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FunDecl {
+    pub(crate) name: String,
+    pub(crate) span: Span,
+    pub(crate) params: Vec<(String, Span)>,
+    pub(crate) body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ClassDecl {
+    pub(crate) name: String,
+    pub(crate) span: Span,
+    pub(crate) superclass: Option<(String, Span)>,
+    pub(crate) fields: Vec<Field>,
+    pub(crate) methods: Vec<Method>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Field {
+    pub(crate) name: String,
+    pub(crate) span: Span,
+    pub(crate) is_pub: bool,
+    pub(crate) initializer: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Method {
+    pub(crate) is_pub: bool,
+    pub(crate) function: FunDecl,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Expr {
+    pub(crate) span: Span,
+    pub(crate) kind: ExprKind,
+}
+
+impl Expr {
+    pub(crate) fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { span, kind }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ExprKind {
+    Nil,
+    Boolean(bool),
+    Int(i64),
+    BigInt(BigInt),
+    Number(f64),
+    String(String),
+    Variable(String),
+    Assign {
+        name: String,
+        value: Box<Expr>,
+    },
+    This,
+    Super(String),
+    Unary {
+        operator: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        operator: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        operator: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: String,
+    },
+    Set {
+        object: Box<Expr>,
+        name: String,
+        value: Box<Expr>,
+    },
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnaryOp {
+    Not,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Less,
+    Equal,
+    Concat,
+    Divide,
+    Greater,
+    FloorDivide,
+    Subtract,
+    Multiply,
+    NotEqual,
+    LessEqual,
+    Remainder,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogicalOp {
+    Or,
+    And,
+}
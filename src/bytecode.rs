@@ -0,0 +1,376 @@
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use crate::class::Class;
+use crate::closure::Closure;
+use crate::error::{ErrorContext, LoxError};
+use crate::function::Function;
+use crate::op::{OpCode, Operand};
+use crate::token::Span;
+use crate::value::Value;
+
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u16 = 6;
+
+const NIL: u8 = 0;
+const NUMBER: u8 = 1;
+const BOOLEAN: u8 = 2;
+const STRING: u8 = 3;
+const FUNCTION: u8 = 4;
+const CLASS: u8 = 5;
+const INT: u8 = 6;
+const BIGINT: u8 = 7;
+
+pub(crate) fn serialize(function: &Function) -> Vec<u8> {
+    let mut body = vec![];
+    write_function(&mut body, function);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_be_bytes());
+    bytes.extend(checksum(&body).to_be_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+pub(crate) fn deserialize(bytes: &[u8]) -> Result<Function, LoxError> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(error("Not a compiled Lox file"));
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(error(
+            format!(
+                "Unsupported bytecode version {}, expected {}",
+                version, VERSION
+            )
+            .as_str(),
+        ));
+    }
+    let expected = reader.u32()?;
+    if checksum(&bytes[reader.offset..]) != expected {
+        return Err(error("Checksum mismatch, the file is corrupt"));
+    }
+
+    let function = reader.function()?;
+    if reader.offset != bytes.len() {
+        return Err(corrupt("trailing data after the script"));
+    }
+    Ok(function)
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &Function) {
+    write_string(bytes, &function.name());
+    write_u32(bytes, function.arity() as usize);
+
+    write_u32(bytes, function.size());
+    bytes.extend(function.codes());
+
+    let mut lines: Vec<(usize, Span)> = vec![];
+    for offset in 0..function.size() {
+        let span = function.span(offset).unwrap_or_default();
+        match lines.last_mut() {
+            Some((count, last)) if *last == span => *count += 1,
+            _ => lines.push((1, span)),
+        }
+    }
+    write_u32(bytes, lines.len());
+    for (count, span) in lines {
+        write_u32(bytes, count);
+        write_u32(bytes, span.line());
+        write_u32(bytes, span.column());
+        write_u32(bytes, span.end().0);
+        write_u32(bytes, span.end().1);
+    }
+
+    let upvalues = function.upvalues();
+    write_u32(bytes, upvalues.len());
+    for (is_local, index) in upvalues {
+        bytes.push(is_local as u8);
+        write_u32(bytes, index);
+    }
+
+    let constants: Vec<&Value> = function.constants().into_iter().collect();
+    write_u32(bytes, constants.len());
+    for constant in constants {
+        write_constant(bytes, constant);
+    }
+}
+
+fn write_constant(bytes: &mut Vec<u8>, constant: &Value) {
+    match constant {
+        Value::Int(value) => {
+            bytes.push(INT);
+            bytes.extend(value.to_be_bytes());
+        }
+        Value::BigInt(value) => {
+            bytes.push(BIGINT);
+            write_string(bytes, &value.to_string());
+        }
+        Value::Number(value) => {
+            bytes.push(NUMBER);
+            bytes.extend(value.to_bits().to_be_bytes());
+        }
+        Value::Boolean(value) => {
+            bytes.push(BOOLEAN);
+            bytes.push(*value as u8);
+        }
+        Value::String(value) => {
+            bytes.push(STRING);
+            write_string(bytes, value);
+        }
+        Value::Function(closure) => {
+            bytes.push(FUNCTION);
+            write_function(bytes, &closure.function());
+        }
+        Value::Class(class) => {
+            bytes.push(CLASS);
+            write_string(bytes, &class.name());
+            write_function(bytes, &class.initializer());
+
+            write_u32(bytes, class.fields().len());
+            for (name, is_pub) in class.fields() {
+                write_string(bytes, name);
+                bytes.push(*is_pub as u8);
+            }
+
+            let methods = class.methods();
+            write_u32(bytes, methods.len());
+            for (method, is_pub) in methods {
+                write_function(bytes, &method);
+                bytes.push(is_pub as u8);
+            }
+        }
+        _ => bytes.push(NIL),
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    write_u32(bytes, value.len());
+    bytes.extend(value.as_bytes());
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u32).to_be_bytes());
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn error(message: &str) -> LoxError {
+    LoxError::new(message, ErrorContext::Bytecode, None)
+}
+
+fn corrupt(reason: &str) -> LoxError {
+    error(format!("Corrupt bytecode file: {}", reason).as_str())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], LoxError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + size)
+            .ok_or_else(|| corrupt("unexpected end of data"))?;
+        self.offset += size;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoxError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoxError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoxError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn size(&mut self) -> Result<usize, LoxError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn flag(&mut self) -> Result<bool, LoxError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            flag => Err(corrupt(format!("invalid flag {}", flag).as_str())),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, LoxError> {
+        let size = self.size()?;
+        String::from_utf8(self.take(size)?.to_vec()).map_err(|_| corrupt("invalid UTF-8 string"))
+    }
+
+    fn function(&mut self) -> Result<Function, LoxError> {
+        let name = self.string()?;
+        let mut function = Function::new(name, self.u32()? as u128);
+
+        let size = self.size()?;
+        let codes = self.take(size)?.to_vec();
+
+        let mut spans = vec![];
+        for _ in 0..self.size()? {
+            let count = self.size()?;
+            let start = (self.size()?, self.size()?);
+            let end = (self.size()?, self.size()?);
+            if spans.len() + count > codes.len() {
+                return Err(corrupt("line table does not match the bytecode"));
+            }
+            spans.extend(std::iter::repeat_n(Span::new(start, end), count));
+        }
+        if spans.len() != codes.len() {
+            return Err(corrupt("line table does not match the bytecode"));
+        }
+
+        for index in 0..self.size()? {
+            let is_local = self.flag()?;
+            let slot = self.size()?;
+            if function.add_upvalue(is_local, slot) != index {
+                return Err(corrupt("duplicate captured variable"));
+            }
+        }
+
+        let mut constants = vec![];
+        for _ in 0..self.size()? {
+            constants.push(self.constant()?);
+        }
+
+        function.set_code(codes, spans);
+        function.set_constants(constants);
+        validate(&function)?;
+        Ok(function)
+    }
+
+    fn constant(&mut self) -> Result<Value, LoxError> {
+        let constant = match self.u8()? {
+            NIL => Value::Nil,
+            NUMBER => Value::Number(f64::from_bits(u64::from_be_bytes(
+                self.take(8)?.try_into().unwrap(),
+            ))),
+            INT => Value::Int(i64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            BIGINT => Value::from(
+                self.string()?
+                    .parse::<BigInt>()
+                    .map_err(|_| corrupt("invalid big integer"))?,
+            ),
+            BOOLEAN => Value::Boolean(self.flag()?),
+            STRING => Value::String(self.string()?),
+            FUNCTION => {
+                let function = self.function()?;
+                Value::Function(Rc::new(Closure::new(Rc::new(function), vec![])))
+            }
+            CLASS => {
+                let name = self.string()?;
+                let mut class = Class::new(name, self.function()?);
+                for _ in 0..self.size()? {
+                    let name = self.string()?;
+                    class.add_field(name, self.flag()?);
+                }
+                for _ in 0..self.size()? {
+                    let method = self.function()?;
+                    class.add_method(method, self.flag()?);
+                }
+                Value::Class(Rc::new(class))
+            }
+            tag => return Err(corrupt(format!("unknown constant tag {}", tag).as_str())),
+        };
+        Ok(constant)
+    }
+}
+
+fn validate(function: &Function) -> Result<(), LoxError> {
+    let constants = function.constants().into_iter().count();
+    let upvalues = function.upvalues().len();
+    let mut boundaries = vec![false; function.size() + 1];
+    let mut jumps = vec![];
+    let mut offset = 0;
+
+    while offset < function.size() {
+        let Some((op, operands, next)) = function.instruction(offset) else {
+            return Err(corrupt(
+                format!("truncated instruction at {} in {}", offset, function).as_str(),
+            ));
+        };
+        boundaries[offset] = true;
+
+        let valid = match op {
+            OpCode::Invalid | OpCode::Wide => {
+                return Err(corrupt(
+                    format!("invalid opcode at {} in {}", offset, function).as_str(),
+                ))
+            }
+            OpCode::Loop => {
+                jumps.push((offset, next.checked_sub(operands[0])));
+                true
+            }
+            OpCode::Jump | OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                jumps.push((offset, Some(next + operands[0])));
+                true
+            }
+            _ if op.operands().first() == Some(&Operand::Short) && operands[0] >= constants => {
+                return Err(corrupt(
+                    format!("constant out of range at {} in {}", offset, function).as_str(),
+                ))
+            }
+            OpCode::GetCaptured | OpCode::SetCaptured => operands[0] < upvalues,
+            OpCode::MakeClosure => match function.constant(operands[0]) {
+                Some(Value::Function(closure)) => captures_fit(&closure.function(), upvalues),
+                _ => false,
+            },
+            OpCode::Class => match function.constant(operands[0]) {
+                Some(Value::Class(class)) => {
+                    captures_fit(&class.initializer(), upvalues)
+                        && class
+                            .methods()
+                            .iter()
+                            .all(|(method, _)| captures_fit(method, upvalues))
+                }
+                _ => false,
+            },
+            _ => true,
+        };
+
+        if !valid {
+            return Err(corrupt(
+                format!("invalid operand at {} in {}", offset, function).as_str(),
+            ));
+        }
+        offset = next;
+    }
+    boundaries[function.size()] = true;
+
+    for (offset, target) in jumps {
+        if target.is_none_or(|target| target > function.size()) {
+            return Err(corrupt(
+                format!("jump out of range at {} in {}", offset, function).as_str(),
+            ));
+        }
+        if target.is_some_and(|target| !boundaries[target]) {
+            return Err(corrupt(
+                format!("jump into an instruction at {} in {}", offset, function).as_str(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn captures_fit(function: &Function, upvalues: usize) -> bool {
+    function
+        .upvalues()
+        .iter()
+        .all(|(is_local, index)| *is_local || *index < upvalues)
+}
@@ -19,15 +19,6 @@ impl<T> Chunk<T> {
     pub(crate) fn get(&self, address: usize) -> Option<&T> {
         self.storage.get(address)
     }
-
-    pub(crate) fn set(&mut self, address: usize, item: T) {
-        self.storage.remove(address);
-        self.storage.insert(address, item);
-    }
-
-    pub(crate) fn size(&self) -> usize {
-        self.storage.len()
-    }
 }
 
 impl<'a, T> IntoIterator for &'a Chunk<T> {
@@ -0,0 +1,197 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+use crate::closure::Closure;
+use crate::function::Function;
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Class {
+    name: String,
+    initializer: Rc<Closure>,
+    fields: Vec<(String, bool)>,
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<String, (Rc<Closure>, bool)>,
+}
+
+impl Class {
+    pub(crate) fn new(name: String, initializer: Function) -> Class {
+        Class {
+            name,
+            initializer: prototype(initializer),
+            fields: vec![],
+            superclass: None,
+            methods: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(crate) fn add_field(&mut self, name: String, is_pub: bool) {
+        self.fields.push((name, is_pub));
+    }
+
+    pub(crate) fn add_method(&mut self, method: Function, is_pub: bool) {
+        self.methods
+            .insert(method.name(), (prototype(method), is_pub));
+    }
+
+    pub(crate) fn initializer(&self) -> Rc<Function> {
+        self.initializer.function()
+    }
+
+    pub(crate) fn fields(&self) -> &Vec<(String, bool)> {
+        &self.fields
+    }
+
+    pub(crate) fn methods(&self) -> Vec<(Rc<Function>, bool)> {
+        let mut methods: Vec<(Rc<Function>, bool)> = self
+            .methods
+            .values()
+            .map(|(method, is_pub)| (method.function(), *is_pub))
+            .collect();
+        methods.sort_by_key(|(method, _)| method.name());
+        methods
+    }
+
+    pub(crate) fn closures_mut(&mut self) -> impl Iterator<Item = &mut Rc<Closure>> {
+        std::iter::once(&mut self.initializer)
+            .chain(self.methods.values_mut().map(|(method, _)| method))
+    }
+
+    pub(crate) fn set_initializer(&mut self, initializer: Function) {
+        self.initializer = prototype(initializer);
+    }
+
+    pub(crate) fn set_superclass(&mut self, superclass: Rc<Class>) {
+        self.superclass = Some(superclass);
+    }
+
+    pub(crate) fn initializers(&self) -> Vec<Rc<Closure>> {
+        let mut initializers = match &self.superclass {
+            Some(superclass) => superclass.initializers(),
+            None => vec![],
+        };
+        initializers.push(self.initializer.clone());
+        initializers
+    }
+
+    pub(crate) fn find_method(&self, name: &String) -> Option<(Rc<Closure>, bool)> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+
+    pub(crate) fn field_visibility(&self, name: &String) -> Option<bool> {
+        match self.fields.iter().find(|(field, _)| field == name) {
+            Some((_, is_pub)) => Some(*is_pub),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.field_visibility(name)),
+        }
+    }
+
+    fn field_names(&self) -> Vec<String> {
+        let mut names = match &self.superclass {
+            Some(superclass) => superclass.field_names(),
+            None => vec![],
+        };
+        names.extend(self.fields.iter().map(|(name, _)| name.clone()));
+        names
+    }
+}
+
+fn prototype(function: Function) -> Rc<Closure> {
+    Rc::new(Closure::new(Rc::new(function), vec![]))
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub(crate) struct Instance {
+    class: Rc<Class>,
+    fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub(crate) fn new(class: Rc<Class>) -> Instance {
+        let fields = class
+            .field_names()
+            .into_iter()
+            .map(|name| (name, Value::Nil))
+            .collect();
+        Instance { class, fields }
+    }
+
+    pub(crate) fn class(&self) -> Rc<Class> {
+        self.class.clone()
+    }
+
+    pub(crate) fn get(&self, name: &String) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+
+    pub(crate) fn set(&mut self, name: &String, value: Value) -> bool {
+        match self.fields.get_mut(name) {
+            Some(field) => {
+                *field = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BoundMethod {
+    method: Rc<Closure>,
+    receiver: Rc<RefCell<Instance>>,
+}
+
+impl BoundMethod {
+    pub(crate) fn new(receiver: Rc<RefCell<Instance>>, method: Rc<Closure>) -> BoundMethod {
+        BoundMethod { method, receiver }
+    }
+
+    pub(crate) fn method(&self) -> Rc<Closure> {
+        self.method.clone()
+    }
+
+    pub(crate) fn receiver(&self) -> Rc<RefCell<Instance>> {
+        self.receiver.clone()
+    }
+
+    pub(crate) fn is(&self, other: &BoundMethod) -> bool {
+        Rc::ptr_eq(&self.receiver, &other.receiver) && Rc::ptr_eq(&self.method, &other.method)
+    }
+}
+
+impl Display for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
@@ -0,0 +1,38 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::function::Function;
+use crate::value::Value;
+
+#[derive(Debug)]
+pub(crate) enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub(crate) struct Closure {
+    function: Rc<Function>,
+    captures: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Closure {
+    pub(crate) fn new(function: Rc<Function>, captures: Vec<Rc<RefCell<Upvalue>>>) -> Closure {
+        Closure { function, captures }
+    }
+
+    pub(crate) fn function(&self) -> Rc<Function> {
+        self.function.clone()
+    }
+
+    pub(crate) fn get_capture(&self, address: usize) -> Option<Rc<RefCell<Upvalue>>> {
+        self.captures.get(address).cloned()
+    }
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
@@ -1,723 +1,644 @@
-use std::iter::Peekable;
-
-use rand::{distributions::Alphanumeric, Rng};
+use std::rc::Rc;
 
+use crate::ast::{BinaryOp, ClassDecl, Expr, ExprKind, FunDecl, LogicalOp, Stmt, UnaryOp};
+use crate::class::Class;
+use crate::closure::Closure;
 use crate::error::{ErrorContext, InterpretResult, LoxError};
 use crate::function::Function;
-use crate::nif::resolve_nif;
-use crate::op::OpCode;
-use crate::scanner::Scanner;
-use crate::token::Kind;
+use crate::op::{OpCode, Operand};
+use crate::optimizer::optimize;
+use crate::parser::Parser;
+use crate::peephole::{narrow_jumps, peephole};
+use crate::resolver::{Binding, Resolution, Resolver};
+use crate::token::Span;
 use crate::value::Value;
 use crate::vm::VM;
 
 pub(crate) struct Compiler<'a> {
     vm: &'a mut VM,
+    source: &'a str,
     scope_depth: u128,
-    globals: Vec<String>,
     errors: Vec<LoxError>,
+    resolution: Resolution,
     functions: Vec<Function>,
-    locals: Vec<Vec<(String, u128)>>,
-    scanner: Peekable<Scanner<'a>>,
+    classes: Vec<Option<String>>,
+    locals: Vec<Vec<(String, u128, Span)>>,
 }
 
 impl<'a> Compiler<'a> {
     pub(crate) fn new(vm: &'a mut VM, function: Function, source: &'a str) -> Compiler<'a> {
         Compiler {
             vm,
-            errors: vec![],
-            globals: vec![],
+            source,
+            classes: vec![],
             locals: vec![vec![]],
             scope_depth: 0,
+            errors: vec![],
+            resolution: Resolution::default(),
             functions: vec![function],
-            scanner: Scanner::new(source).peekable(),
         }
     }
 
     pub(crate) fn compile(&mut self) -> Result<Function, InterpretResult> {
-        loop {
-            self.compile_declaration();
-            match self.scanner.peek().unwrap().kind() {
-                Kind::Eof => break,
-                _ => continue,
+        let statements = match Parser::new(self.source).parse() {
+            Ok(statements) => statements,
+            Err(errors) => return Err(self.report(errors)),
+        };
+
+        match Resolver::new().resolve(&statements) {
+            (Ok(resolution), warnings) => {
+                self.report(warnings);
+                self.resolution = resolution;
+            }
+            (Err(errors), warnings) => {
+                return Err(self.report(errors.into_iter().chain(warnings).collect()))
             }
         }
 
-        match self.errors.len() {
-            0 => return Ok(self.function().clone()),
-            _ => {
-                self.errors.iter().for_each(|e| eprintln!("{}", e));
-                Err(InterpretResult::CompileError)
-            }
+        let statements = match self.vm.optimize() {
+            true => optimize(statements),
+            false => statements,
+        };
+        statements
+            .iter()
+            .for_each(|statement| self.compile_statement(statement));
+        if !self.errors.is_empty() {
+            let errors = std::mem::take(&mut self.errors);
+            return Err(self.report(errors));
         }
-    }
 
-    fn compile_declaration(&mut self) {
-        match self.scanner.peek() {
-            Some(token) => match token.kind() {
-                Kind::Let => {
-                    self.scanner.next();
-                    self.compile_let();
-                }
+        let mut function = self.function().clone();
+        match self.vm.optimize() {
+            true => peephole(&mut function),
+            false => narrow_jumps(&mut function),
+        }
+        Ok(function)
+    }
 
-                Kind::Fun => {
-                    self.scanner.next();
-                    self.compile_fun();
-                }
+    fn report(&mut self, mut errors: Vec<LoxError>) -> InterpretResult {
+        errors.sort_by_key(|error| {
+            error
+                .span()
+                .map_or((usize::MAX, 0), |span| (span.line(), span.column()))
+        });
+        errors.iter().for_each(|error| self.vm.report(error));
+        InterpretResult::CompileError
+    }
 
-                Kind::Return => {
-                    self.scanner.next();
-                    self.compile_expression();
-                    self.expect(Kind::Semicolon);
-
-                    if self.function().is_loop() {
-                        loop {
-                            self.scope_depth -= 1;
-                            self.function().add_op(OpCode::Return);
-                            let function = self.functions.pop().unwrap();
-                            self.locals.pop();
-                            self.vm.add_loop(function.clone());
-
-                            self.function().add_op(OpCode::Loop);
-                            self.add_constant(Value::String(function.name()));
-
-                            if !self.function().is_loop() {
-                                break;
-                            }
-                        }
+    fn compile_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Let {
+                name,
+                span,
+                initializer,
+            } => {
+                match initializer {
+                    Some(initializer) => self.compile_expression(initializer),
+                    None => {
+                        self.set_span(*span);
+                        self.function().add_op(OpCode::Nil);
                     }
-
-                    self.function().add_op(OpCode::Return);
-                    self.function().already_returns();
                 }
+                self.define_variable(name.clone(), *span);
+            }
 
-                _ => self.compile_statement(true),
-            },
+            Stmt::Fun(declaration) => self.compile_fun(declaration),
 
-            None => self.errors.push(LoxError::new(
-                "Unexpected end of script",
-                ErrorContext::Compile,
-                None,
-            )),
-        }
-    }
+            Stmt::Class(declaration) => self.compile_class(declaration),
 
-    fn compile_let(&mut self) {
-        match self.scanner.next() {
-            Some(token) if token.kind() == Kind::Identifier => {
-                let variable_name = token.value().unwrap();
-
-                match self.scanner.peek() {
-                    Some(token) => match token.kind() {
-                        Kind::Equal => {
-                            self.scanner.next();
-                            self.compile_expression();
-                        }
-
-                        _ => self.function().add_op(OpCode::Nil),
-                    },
-
-                    None => self.errors.push(LoxError::new(
-                        "Unexpected end of script",
-                        ErrorContext::Compile,
-                        None,
-                    )),
+            Stmt::Return { span, value } => {
+                match value {
+                    Some(value) => self.compile_expression(value),
+                    None => {
+                        self.set_span(*span);
+                        self.function().add_op(OpCode::Nil);
+                    }
                 }
-                self.expect(Kind::Semicolon);
+                self.set_span(*span);
+                self.function().add_op(OpCode::Return);
+            }
 
-                match self.scope_depth {
-                    0 => {
-                        self.globals.push(variable_name.clone().into());
-                        self.function().add_op(OpCode::DefGlobal);
-                        self.add_constant(variable_name);
-                    }
+            Stmt::Expression(expression) => {
+                self.compile_expression(expression);
+                self.function().add_op(OpCode::Pop);
+            }
 
-                    _ => {
-                        let variable_name: String = variable_name.into();
-
-                        if variable_name != *"_" {
-                            let current_scope = self.scope_depth;
-                            match self.locals().iter().find(|(name, scope)| {
-                                *name == variable_name && *scope == current_scope
-                            }) {
-                                Some(_) => self.errors.push(LoxError::new(
-                                    format!("Variable {:?} is already defined", variable_name)
-                                        .as_str(),
-                                    ErrorContext::Compile,
-                                    None,
-                                )),
-                                None => self.locals().push((variable_name, current_scope)),
-                            }
-                        }
-                    }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.compile_expression(condition);
+                let jump_address = self.function().add_jump(true);
+                self.function().add_op(OpCode::Pop);
+                self.compile_statement(then_branch);
+                let else_jump_address = self.function().add_jump(false);
+                self.patch_jump(jump_address);
+                self.function().add_op(OpCode::Pop);
+
+                if let Some(else_branch) = else_branch {
+                    self.compile_statement(else_branch);
                 }
+
+                self.patch_jump(else_jump_address);
             }
 
-            Some(token) => self.errors.push(LoxError::new(
-                format!("unexpected {:?} #1", token).as_str(),
-                ErrorContext::Compile,
-                None,
-            )),
+            Stmt::While { condition, body } => {
+                let loop_start = self.function().size();
+                self.compile_expression(condition);
 
-            None => self.errors.push(LoxError::new(
-                "Unexpected end of script",
-                ErrorContext::Compile,
-                None,
-            )),
-        }
-    }
+                let jump_address = self.function().add_jump(true);
+                self.function().add_op(OpCode::Pop);
+                self.compile_statement(body);
+                self.add_loop(loop_start);
 
-    fn compile_fun(&mut self) {
-        match self.scanner.next() {
-            Some(token) if token.kind() == Kind::Identifier => {
-                let function_name: String = token.value().unwrap().into();
-
-                if self.vm.function_exists(self.scope_depth, &function_name)
-                    || resolve_nif(&function_name).is_some()
-                {
-                    self.errors.push(LoxError::new(
-                        format!("Function {} already exists", function_name).as_str(),
-                        ErrorContext::Compile,
-                        None,
-                    ));
-                    return;
-                }
+                self.patch_jump(jump_address);
+                self.function().add_op(OpCode::Pop);
+            }
 
-                self.expect(Kind::LeftParen);
-                self.scope_depth += 1;
-                self.locals.push(vec![]);
-                let mut arity = 0;
-
-                loop {
-                    match self.scanner.next() {
-                        Some(token) if token.kind() == Kind::Identifier => {
-                            arity += 1;
-                            let variable_name: String = token.value().unwrap().into();
-                            let current_scope = self.scope_depth;
-                            self.locals().push((variable_name, current_scope));
-
-                            match self.scanner.peek() {
-                                Some(token) if token.kind() == Kind::Comma => {
-                                    self.scanner.next();
-                                    continue;
-                                }
-
-                                Some(token) if token.kind() == Kind::RightParen => {
-                                    self.scanner.next();
-                                    break;
-                                }
-
-                                None => self.errors.push(LoxError::new(
-                                    "Unexpected end of script",
-                                    ErrorContext::Compile,
-                                    None,
-                                )),
-
-                                _ => self.errors.push(LoxError::new(
-                                    format!("unexpected {:?} #1", token).as_str(),
-                                    ErrorContext::Compile,
-                                    None,
-                                )),
-                            }
-                        }
-
-                        Some(token) if token.kind() == Kind::RightParen => {
-                            break;
-                        }
-
-                        None => self.errors.push(LoxError::new(
-                            "Unexpected end of script",
-                            ErrorContext::Compile,
-                            None,
-                        )),
-
-                        _ => self.errors.push(LoxError::new(
-                            format!("unexpected {:?} #1", token).as_str(),
-                            ErrorContext::Compile,
-                            None,
-                        )),
-                    }
-                }
+            Stmt::For {
+                span,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => self.compile_for(*span, initializer, condition, increment, body),
 
-                self.new_function(function_name, arity);
-                self.compile_statement(false);
-                if let Some(false) = self.function().has_return() {
-                    self.function().add_op(OpCode::Nil);
-                    self.function().add_op(OpCode::Return);
-                }
+            Stmt::Block(statements) => {
+                self.scope_depth += 1;
+                statements
+                    .iter()
+                    .for_each(|statement| self.compile_statement(statement));
+                self.pop_scope();
                 self.scope_depth -= 1;
-                let function = self.functions.pop().unwrap();
-                self.locals.pop();
-                let address = self.vm.add_function(self.scope_depth, function);
-                if self.scope_depth > 0 {
-                    self.function().add_op(OpCode::MakeClosure);
-                    self.add_constant(Value::Number(address as f64));
-                }
             }
+        }
+    }
 
-            None => self.errors.push(LoxError::new(
-                "Unexpected end of script",
-                ErrorContext::Compile,
-                None,
-            )),
+    fn compile_fun(&mut self, declaration: &FunDecl) {
+        let name = &declaration.name;
+        if self.scope_depth > 0 {
+            self.define_variable(name.clone(), declaration.span);
+        }
 
-            Some(token) => self.errors.push(LoxError::new(
-                format!("unexpected {:?} #1", token).as_str(),
-                ErrorContext::Compile,
-                None,
-            )),
+        self.scope_depth += 1;
+        self.locals.push(vec![]);
+        let function = self.compile_body(declaration);
+        let prototype = Closure::new(Rc::new(function), vec![]);
+        self.set_span(declaration.span);
+        let address = self
+            .function()
+            .add_constant(Value::Function(Rc::new(prototype)));
+        self.add_instruction(OpCode::MakeClosure, &[address]);
+        if self.scope_depth == 0 {
+            self.define_variable(name.clone(), declaration.span);
         }
     }
 
-    fn compile_statement(&mut self, manage_scope: bool) {
-        match self.scanner.peek() {
-            Some(token) if token.kind() == Kind::If => {
-                self.scanner.next();
-                self.compile_if();
-            }
+    fn compile_class(&mut self, declaration: &ClassDecl) {
+        let class_name = &declaration.name;
+        let superclass = declaration.superclass.clone();
+        self.classes.push(
+            superclass
+                .as_ref()
+                .map(|(superclass, _)| superclass.clone()),
+        );
+
+        if self.scope_depth > 0 {
+            self.define_variable(class_name.clone(), declaration.span);
+        }
 
-            Some(token) if token.kind() == Kind::While => {
-                self.scanner.next();
-                self.compile_while();
+        let mut class = Class::new(class_name.clone(), Function::new(String::new(), 0));
+        self.scope_depth += 1;
+        self.locals.push(vec![(
+            "this".to_string(),
+            self.scope_depth,
+            declaration.span,
+        )]);
+        self.new_function("##FIELDS##".to_string(), 0, declaration.span);
+        self.add_upvalues(declaration.span, "##FIELDS##");
+
+        for field in declaration.fields.iter() {
+            self.set_span(field.span);
+            self.add_instruction(OpCode::GetLocal, &[0]);
+            match &field.initializer {
+                Some(initializer) => self.compile_expression(initializer),
+                None => self.function().add_op(OpCode::Nil),
             }
 
-            Some(token) if token.kind() == Kind::LeftBrace => {
-                self.scanner.next();
-                if manage_scope {
-                    self.scope_depth += 1;
-                }
-                loop {
-                    match self.scanner.peek() {
-                        Some(token) => match token.kind() {
-                            Kind::RightBrace | Kind::Eof => break,
-                            _ => (),
-                        },
-
-                        None => self.errors.push(LoxError::new(
-                            "Unexpected end of script",
-                            ErrorContext::Compile,
-                            None,
-                        )),
-                    }
-                    self.compile_declaration();
-                }
-                self.expect(Kind::RightBrace);
+            self.set_span(field.span);
+            self.add_property_op(OpCode::SetProperty, field.name.clone(), true);
+            self.function().add_op(OpCode::Pop);
+            class.add_field(field.name.clone(), field.is_pub);
+        }
 
-                let current_scope = self.scope_depth;
-                self.locals().retain(|(_, scope)| *scope != current_scope);
+        let initializer = self.end_function();
+        class.set_initializer(initializer);
+
+        for method in declaration.methods.iter() {
+            self.scope_depth += 1;
+            self.locals.push(vec![(
+                "this".to_string(),
+                self.scope_depth,
+                method.function.span,
+            )]);
+            let method_function = self.compile_body(&method.function);
+            class.add_method(method_function, method.is_pub);
+        }
+        self.classes.pop();
 
-                if manage_scope {
-                    self.scope_depth -= 1;
-                }
-            }
+        let has_superclass = superclass.is_some();
+        if let Some((superclass, span)) = superclass {
+            self.set_span(span);
+            self.compile_variable(&superclass, span);
+        }
+        self.set_span(declaration.span);
+        let address = self.function().add_constant(Value::Class(Rc::new(class)));
+        self.add_instruction(OpCode::Class, &[address, has_superclass as usize]);
+        if self.scope_depth == 0 {
+            self.define_variable(class_name.clone(), declaration.span);
+        }
+    }
 
-            None => {
-                self.errors.push(LoxError::new(
-                    "Unexpected end of script",
-                    ErrorContext::Compile,
-                    None,
-                ));
-            }
+    fn compile_body(&mut self, declaration: &FunDecl) -> Function {
+        let current_scope = self.scope_depth;
+        declaration
+            .params
+            .iter()
+            .for_each(|(param, span)| self.locals().push((param.clone(), current_scope, *span)));
+
+        self.new_function(
+            declaration.name.clone(),
+            declaration.params.len() as u128,
+            declaration.span,
+        );
+        self.add_upvalues(declaration.span, &declaration.name);
+        declaration
+            .body
+            .iter()
+            .for_each(|statement| self.compile_statement(statement));
+        self.pop_scope();
+        self.end_function()
+    }
 
-            _ => {
-                self.compile_expression();
-                self.expect(Kind::Semicolon);
-            }
+    fn end_function(&mut self) -> Function {
+        self.function().add_op(OpCode::Nil);
+        self.function().add_op(OpCode::Return);
+        self.scope_depth -= 1;
+        self.locals.pop();
+        let mut function = self.functions.pop().unwrap();
+        match self.vm.optimize() {
+            true => peephole(&mut function),
+            false => narrow_jumps(&mut function),
         }
+        function
     }
 
-    fn compile_if(&mut self) {
-        self.compile_expression();
+    fn compile_for(
+        &mut self,
+        span: Span,
+        initializer: &Option<Box<Stmt>>,
+        condition: &Option<Expr>,
+        increment: &Option<Expr>,
+        body: &Stmt,
+    ) {
+        self.scope_depth += 1;
+        self.set_span(span);
+
+        let variable = match initializer {
+            Some(initializer) => {
+                let size = self.locals().len();
+                self.compile_statement(initializer);
+                match self.locals().len() > size {
+                    true => Some(self.locals().len() - 1),
+                    false => None,
+                }
+            }
+            None => None,
+        };
+
+        let mut loop_start = self.function().size();
+        match condition {
+            Some(condition) => self.compile_expression(condition),
+            None => self.add_constant(Value::Boolean(true)),
+        }
+
         let jump_address = self.function().add_jump(true);
         self.function().add_op(OpCode::Pop);
-        self.compile_statement(true);
-        let else_jump_address = self.function().add_jump(false);
-        self.function().patch_jump(jump_address);
-        self.function().add_op(OpCode::Pop);
 
-        if let Some(token) = self.scanner.peek() {
-            if token.kind() == Kind::Else {
-                self.scanner.next();
-                self.compile_statement(true);
-            }
+        if let Some(increment) = increment {
+            let body_address = self.function().add_jump(false);
+            let increment_start = self.function().size();
+            self.compile_expression(increment);
+            self.function().add_op(OpCode::Pop);
+            self.add_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_address);
         }
 
-        self.function().patch_jump(else_jump_address);
-    }
+        match variable {
+            Some(outer) => {
+                let variable = self.locals()[outer].0.clone();
+                self.scope_depth += 1;
+                self.add_instruction(OpCode::GetLocal, &[outer]);
+                self.define_variable(variable, span);
+                let inner = self.locals().len() - 1;
 
-    fn compile_while(&mut self) {
-        let name: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
+                self.compile_statement(body);
 
-        self.scope_depth += 1;
-        self.locals.push(vec![]);
-        self.new_loop(name.clone());
+                self.add_instruction(OpCode::GetLocal, &[inner]);
+                self.add_instruction(OpCode::SetLocal, &[outer]);
+                self.function().add_op(OpCode::Pop);
+                self.pop_scope();
+                self.scope_depth -= 1;
+            }
 
-        self.compile_expression();
+            None => self.compile_statement(body),
+        }
+        self.add_loop(loop_start);
 
-        let jump_address = self.function().add_jump(true);
+        self.patch_jump(jump_address);
         self.function().add_op(OpCode::Pop);
 
-        self.compile_statement(false);
+        self.pop_scope();
+        self.scope_depth -= 1;
+    }
 
-        if self.function().is_loop() {
-            self.function().add_op(OpCode::Loop);
-            self.add_constant(Value::String(name.clone()));
+    fn compile_expression(&mut self, expression: &Expr) {
+        let span = expression.span;
 
-            self.function().patch_jump(jump_address);
-            self.function().add_op(OpCode::Pop);
+        match &expression.kind {
+            ExprKind::Nil => {
+                self.set_span(span);
+                self.function().add_op(OpCode::Nil);
+            }
 
-            self.scope_depth -= 1;
-            let function = self.functions.pop().unwrap();
-            self.locals.pop();
-            self.vm.add_loop(function);
+            ExprKind::Boolean(value) => {
+                self.set_span(span);
+                self.add_constant(Value::Boolean(*value));
+            }
 
-            self.function().add_op(OpCode::Loop);
-            self.add_constant(Value::String(name));
-        }
-    }
+            ExprKind::Int(value) => {
+                self.set_span(span);
+                self.add_constant(Value::Int(*value));
+            }
 
-    fn compile_expression(&mut self) {
-        self.compile_term(true);
-        loop {
-            match self.scanner.peek() {
-                Some(token) if token.kind() == Kind::Minus => {
-                    self.compile_term(false);
-                    self.function().add_op(OpCode::Add);
-                }
+            ExprKind::BigInt(value) => {
+                self.set_span(span);
+                self.add_constant(Value::from(value.clone()));
+            }
 
-                Some(token) if token.kind() == Kind::Plus => {
-                    self.scanner.next();
-                    self.compile_term(false);
-                    self.function().add_op(OpCode::Add);
-                }
+            ExprKind::Number(value) => {
+                self.set_span(span);
+                self.add_constant(Value::Number(*value));
+            }
 
-                Some(token) if token.kind() == Kind::Concat => {
-                    self.scanner.next();
-                    self.compile_term(false);
-                    self.function().add_op(OpCode::Concat)
-                }
+            ExprKind::String(value) => {
+                self.set_span(span);
+                self.add_constant(Value::String(value.clone()));
+            }
 
-                Some(token) if token.kind() == Kind::Or => {
-                    self.scanner.next();
-                    let else_jump_address = self.function().add_jump(true);
-                    let end_jump_address = self.function().add_jump(false);
-                    self.function().patch_jump(else_jump_address);
-                    self.function().add_op(OpCode::Pop);
-                    self.compile_term(false);
-                    self.function().patch_jump(end_jump_address);
-                }
+            ExprKind::Variable(name) => {
+                self.set_span(span);
+                self.compile_variable(name, span);
+            }
 
-                Some(_) => break,
+            ExprKind::Assign { name, value } => {
+                self.compile_expression(value);
+                self.set_span(span);
+                match self.resolution.binding(span, name) {
+                    Binding::Local(address) => {
+                        self.add_instruction(OpCode::SetLocal, &[address]);
+                    }
 
-                None => self.errors.push(LoxError::new(
-                    "Unexpected end of script",
-                    ErrorContext::Compile,
-                    None,
-                )),
-            };
-        }
-    }
+                    Binding::Upvalue(address) => {
+                        self.add_instruction(OpCode::SetCaptured, &[address]);
+                    }
 
-    fn compile_term(&mut self, can_assign: bool) {
-        self.compile_factor(can_assign);
-        loop {
-            match self.scanner.peek() {
-                Some(token) if token.kind() == Kind::Star => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Multiply);
+                    Binding::Global => {
+                        let address = self.function().add_constant(Value::String(name.clone()));
+                        self.add_instruction(OpCode::SetGlobal, &[address]);
+                    }
                 }
+            }
 
-                Some(token) if token.kind() == Kind::Slash => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Divide);
-                }
+            ExprKind::This => {
+                self.set_span(span);
+                self.compile_variable("this", span);
+            }
 
-                Some(token) if token.kind() == Kind::Percent => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Rem);
-                }
+            ExprKind::Super(name) => {
+                let superclass = self.superclass();
+                self.set_span(span);
+                self.compile_variable("this", span);
+                self.compile_variable(&superclass, span);
+                let address = self.function().add_constant(Value::String(name.clone()));
+                self.add_instruction(OpCode::GetSuper, &[address]);
+            }
 
-                Some(token) if token.kind() == Kind::And => {
-                    self.scanner.next();
-                    let jump_address = self.function().add_jump(true);
-                    self.function().add_op(OpCode::Pop);
-                    self.compile_factor(false);
-                    self.function().patch_jump(jump_address);
+            ExprKind::Unary { operator, operand } => {
+                self.compile_expression(operand);
+                self.set_span(operand.span);
+                match operator {
+                    UnaryOp::Not => self.function().add_op(OpCode::Not),
+                    UnaryOp::Negate => self.function().add_op(OpCode::Negate),
                 }
+            }
 
-                Some(token) if token.kind() == Kind::EqualEqual => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Equal);
-                }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                self.compile_expression(left);
+                self.compile_expression(right);
+                self.set_span(span);
+                let op = match operator {
+                    BinaryOp::Add => OpCode::Add,
+                    BinaryOp::Less => OpCode::Less,
+                    BinaryOp::Equal => OpCode::Equal,
+                    BinaryOp::Concat => OpCode::Concat,
+                    BinaryOp::Divide => OpCode::Divide,
+                    BinaryOp::FloorDivide => OpCode::FloorDivide,
+                    BinaryOp::Greater => OpCode::Greater,
+                    BinaryOp::Multiply => OpCode::Multiply,
+                    BinaryOp::Subtract => OpCode::Subtract,
+                    BinaryOp::NotEqual => OpCode::NotEqual,
+                    BinaryOp::LessEqual => OpCode::LessEqual,
+                    BinaryOp::Remainder => OpCode::Rem,
+                    BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+                };
+                self.function().add_op(op);
+            }
 
-                Some(token) if token.kind() == Kind::BangEqual => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::NotEqual);
-                }
+            ExprKind::Logical {
+                operator,
+                left,
+                right,
+            } => {
+                self.compile_expression(left);
+                match operator {
+                    LogicalOp::Or => {
+                        let else_jump_address = self.function().add_jump(true);
+                        let end_jump_address = self.function().add_jump(false);
+                        self.patch_jump(else_jump_address);
+                        self.function().add_op(OpCode::Pop);
+                        self.compile_expression(right);
+                        self.patch_jump(end_jump_address);
+                    }
 
-                Some(token) if token.kind() == Kind::GreaterEqual => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::GreaterEqual);
+                    LogicalOp::And => {
+                        let jump_address = self.function().add_jump(true);
+                        self.function().add_op(OpCode::Pop);
+                        self.compile_expression(right);
+                        self.patch_jump(jump_address);
+                    }
                 }
+            }
 
-                Some(token) if token.kind() == Kind::Greater => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Greater);
+            ExprKind::Call { callee, args } => match &callee.kind {
+                ExprKind::Get { object, name } => {
+                    let via_this = matches!(object.kind, ExprKind::This);
+                    self.compile_expression(object);
+                    args.iter().for_each(|arg| self.compile_expression(arg));
+                    self.set_span(span);
+                    let address = self.function().add_constant(Value::String(name.clone()));
+                    self.add_instruction(OpCode::Invoke, &[address, args.len(), via_this as usize]);
                 }
 
-                Some(token) if token.kind() == Kind::LessEqual => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::LessEqual);
+                ExprKind::Super(name) => {
+                    let superclass = self.superclass();
+                    self.set_span(callee.span);
+                    self.compile_variable("this", callee.span);
+                    args.iter().for_each(|arg| self.compile_expression(arg));
+                    self.set_span(span);
+                    self.compile_variable(&superclass, callee.span);
+                    let address = self.function().add_constant(Value::String(name.clone()));
+                    self.add_instruction(OpCode::InvokeSuper, &[address, args.len()]);
                 }
 
-                Some(token) if token.kind() == Kind::Less => {
-                    self.scanner.next();
-                    self.compile_factor(false);
-                    self.function().add_op(OpCode::Less);
+                _ => {
+                    self.compile_expression(callee);
+                    args.iter().for_each(|arg| self.compile_expression(arg));
+                    self.set_span(span);
+                    self.add_instruction(OpCode::Call, &[args.len()]);
                 }
+            },
 
-                Some(_) => break,
-
-                None => self.errors.push(LoxError::new(
-                    "Unexpected end of script",
-                    ErrorContext::Compile,
-                    None,
-                )),
-            };
-        }
-    }
-
-    fn compile_factor(&mut self, can_assign: bool) {
-        match self.scanner.next() {
-            Some(token) if token.kind() == Kind::Nil => self.function().add_op(OpCode::Nil),
-            Some(token) if [Kind::Number, Kind::String].contains(&token.kind()) => {
-                self.add_constant(token.value().unwrap())
+            ExprKind::Get { object, name } => {
+                let via_this = matches!(object.kind, ExprKind::This);
+                self.compile_expression(object);
+                self.set_span(span);
+                self.add_property_op(OpCode::GetProperty, name.clone(), via_this);
             }
-            Some(token) if token.kind() == Kind::True => self.add_constant(Value::Boolean(true)),
-            Some(token) if token.kind() == Kind::False => self.add_constant(Value::Boolean(false)),
 
-            Some(token) if token.kind() == Kind::Not => {
-                self.compile_factor(can_assign);
-                self.function().add_op(OpCode::Not);
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                let via_this = matches!(object.kind, ExprKind::This);
+                self.compile_expression(object);
+                self.compile_expression(value);
+                self.set_span(span);
+                self.add_property_op(OpCode::SetProperty, name.clone(), via_this);
             }
 
-            Some(token) if token.kind() == Kind::Minus => {
-                self.compile_factor(can_assign);
-                self.function().add_op(OpCode::Negate);
+            ExprKind::List(elements) => {
+                elements
+                    .iter()
+                    .for_each(|element| self.compile_expression(element));
+                self.set_span(span);
+                self.add_instruction(OpCode::BuildList, &[elements.len()]);
             }
 
-            Some(token) if token.kind() == Kind::LeftParen => {
-                self.compile_expression();
-                match self.scanner.peek() {
-                    Some(token) if token.kind() == Kind::RightParen => {
-                        self.scanner.next();
-                    }
-
-                    Some(_) => self.errors.push(LoxError::new(
-                        format!("unexpected {:?} #2", token).as_str(),
-                        ErrorContext::Compile,
-                        None,
-                    )),
-
-                    None => self.errors.push(LoxError::new(
-                        "Unexpected end of script",
-                        ErrorContext::Compile,
-                        None,
-                    )),
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.compile_expression(key);
+                    self.compile_expression(value);
                 }
+                self.set_span(span);
+                self.add_instruction(OpCode::BuildMap, &[entries.len()]);
             }
 
-            Some(token) if token.kind() == Kind::Identifier => {
-                let name: String = token.value().unwrap().into();
-                let address = self.resolve_local(name.clone());
-
-                match self.scanner.peek().cloned() {
-                    Some(token) if token.kind() == Kind::Equal && can_assign => {
-                        self.scanner.next();
-                        self.compile_expression();
-                        match address {
-                            Some(address) => {
-                                self.function().add_op(OpCode::SetLocal);
-                                self.function().add_address(address as usize);
-                            }
-
-                            None => match self.globals.iter().find(|variable| **variable == name) {
-                                Some(_) => {
-                                    self.function().add_op(OpCode::SetGlobal);
-                                    self.add_constant(Value::String(name));
-                                }
-                                None => {
-                                    self.errors.push(LoxError::new(
-                                        "Cannot assign to captured variable",
-                                        ErrorContext::Compile,
-                                        None,
-                                    ));
-                                }
-                            },
-                        }
-                    }
-
-                    Some(token) if token.kind() == Kind::Equal => {
-                        self.scanner.next();
-                        self.errors.push(LoxError::new(
-                            "Invalid assignment target",
-                            ErrorContext::Compile,
-                            None,
-                        ));
-                    }
-
-                    Some(_token) if _token.kind() == Kind::LeftParen => {
-                        self.scanner.next();
-                        let mut args = 0;
-                        loop {
-                            match self.scanner.peek() {
-                                Some(token) if token.kind() == Kind::RightParen => {
-                                    self.scanner.next();
-                                    break;
-                                }
-
-                                Some(_) => {
-                                    self.compile_expression();
-                                    args += 1;
-                                    match self.scanner.peek() {
-                                        Some(token) if token.kind() == Kind::Comma => {
-                                            self.scanner.next();
-                                            continue;
-                                        }
-
-                                        Some(token) if token.kind() == Kind::RightParen => {
-                                            self.scanner.next();
-                                            break;
-                                        }
-
-                                        None => self.errors.push(LoxError::new(
-                                            "Unexpected end of script",
-                                            ErrorContext::Compile,
-                                            None,
-                                        )),
-
-                                        _ => self.errors.push(LoxError::new(
-                                            format!("unexpected {:?} #1", token).as_str(),
-                                            ErrorContext::Compile,
-                                            None,
-                                        )),
-                                    }
-                                }
-
-                                None => self.errors.push(LoxError::new(
-                                    "Unexpected end of script",
-                                    ErrorContext::Compile,
-                                    None,
-                                )),
-                            }
-                        }
-
-                        self.function().add_op(OpCode::Call);
-                        self.add_constant(Value::Number(self.scope_depth as f64));
-                        self.add_constant(Value::Number(args.into()));
-                        self.add_constant(token.value().unwrap());
-                    }
+            ExprKind::Index { object, index } => {
+                self.compile_expression(object);
+                self.compile_expression(index);
+                self.set_span(span);
+                self.function().add_op(OpCode::GetIndex);
+            }
 
-                    _ if address.is_some() => {
-                        self.function().add_op(OpCode::GetLocal);
-                        self.function().add_address(address.unwrap() as usize);
-                    }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.compile_expression(object);
+                self.compile_expression(index);
+                self.compile_expression(value);
+                self.set_span(span);
+                self.function().add_op(OpCode::SetIndex);
+            }
+        }
+    }
 
-                    _ if self.vm.function_exists(self.scope_depth, &name) => {
-                        let (_, address) =
-                            self.vm.resolve_function(&name, self.scope_depth).unwrap();
-                        self.add_constant(Value::Function((address, None)));
-                    }
+    fn superclass(&self) -> String {
+        self.classes.last().cloned().flatten().unwrap_or_default()
+    }
 
-                    _ => {
-                        let captured = match self.locals.as_slice().split_last() {
-                            Some((_, captured_frames)) => captured_frames
-                                .iter()
-                                .enumerate()
-                                .rev()
-                                .map(|(index, frame)| (index, frame.iter().enumerate()))
-                                .find_map(|(frame_index, mut frame)| {
-                                    frame.find_map(|(index, item)| match item.0 == name {
-                                        true => Some((frame_index, index)),
-                                        false => None,
-                                    })
-                                }),
-
-                            None => None,
-                        };
-
-                        match captured {
-                            Some((frame, address)) => {
-                                self.function().add_op(OpCode::GetCaptured);
-                                self.add_constant(Value::String(name.clone()));
-                                self.function().add_capture(name, frame, address);
-                            }
-
-                            None => {
-                                self.function().add_op(OpCode::GetGlobal);
-                                self.add_constant(Value::String(name));
-                            }
-                        }
-                    }
-                }
+    fn compile_variable(&mut self, name: &str, span: Span) {
+        match self.resolution.binding(span, name) {
+            Binding::Local(address) => {
+                self.add_instruction(OpCode::GetLocal, &[address]);
             }
 
-            Some(token) => self.errors.push(LoxError::new(
-                format!("unexpected {:?} #3", token).as_str(),
-                ErrorContext::Compile,
-                None,
-            )),
+            Binding::Upvalue(address) => {
+                self.add_instruction(OpCode::GetCaptured, &[address]);
+            }
 
-            None => self.errors.push(LoxError::new(
-                "Unexpected end of script",
-                ErrorContext::Compile,
-                None,
-            )),
+            Binding::Global => {
+                let address = self
+                    .function()
+                    .add_constant(Value::String(name.to_string()));
+                self.add_instruction(OpCode::GetGlobal, &[address]);
+            }
         }
     }
 
-    fn expect(&mut self, kind: Kind) {
-        match self.scanner.peek() {
-            Some(token) if token.kind() == kind => {
-                self.scanner.next();
+    fn define_variable(&mut self, variable_name: String, span: Span) {
+        self.set_span(span);
+        match self.scope_depth {
+            0 => {
+                let address = self.function().add_constant(Value::String(variable_name));
+                self.add_instruction(OpCode::DefGlobal, &[address]);
             }
 
-            Some(token) => self.errors.push(LoxError::new(
-                format!("expected {:?}, got {:?}", kind, token).as_str(),
-                ErrorContext::Compile,
-                None,
-            )),
-
-            None => self.errors.push(LoxError::new(
-                "Unexpected end of script",
-                ErrorContext::Compile,
-                None,
-            )),
+            _ => {
+                if variable_name == *"_" {
+                    self.function().add_op(OpCode::Pop);
+                } else {
+                    let current_scope = self.scope_depth;
+                    self.locals().push((variable_name, current_scope, span));
+                }
+            }
         }
     }
 
-    fn resolve_local(&mut self, name: String) -> Option<u128> {
-        self.locals()
-            .iter()
-            .enumerate()
-            .rev()
-            .find(|(_, item)| item.0 == name)
-            .map(|(index, _)| index as u128)
+    fn pop_scope(&mut self) {
+        let current_scope = self.scope_depth;
+        while let Some((name, scope, span)) = self.locals().last().cloned() {
+            if scope != current_scope {
+                break;
+            }
+            self.locals().pop();
+            match self.resolution.is_captured(span, &name) {
+                true => self.function().add_op(OpCode::CloseCaptured),
+                false => self.function().add_op(OpCode::Pop),
+            }
+        }
     }
 
-    fn locals(&mut self) -> &mut Vec<(String, u128)> {
+    fn locals(&mut self) -> &mut Vec<(String, u128, Span)> {
         self.locals.last_mut().unwrap()
     }
 
@@ -725,19 +646,75 @@ impl<'a> Compiler<'a> {
         self.functions.last_mut().unwrap()
     }
 
+    fn set_span(&mut self, span: Span) {
+        self.function().set_span(span);
+    }
+
+    fn add_property_op(&mut self, op: OpCode, name: String, via_this: bool) {
+        let address = self.function().add_constant(Value::String(name));
+        self.add_instruction(op, &[address, via_this as usize]);
+    }
+
+    fn patch_jump(&mut self, address: usize) {
+        if !self.function().patch_jump(address) {
+            let span = self.function().span(address);
+            self.errors.push(LoxError::new(
+                "Too much code to jump over",
+                ErrorContext::Compile,
+                span,
+            ));
+        }
+    }
+
+    fn add_loop(&mut self, start: usize) {
+        if !self.function().add_loop(start) {
+            let span = self.function().span(start);
+            self.errors.push(LoxError::new(
+                "Loop body is too large",
+                ErrorContext::Compile,
+                span,
+            ));
+        }
+    }
+
+    fn add_instruction(&mut self, op: OpCode, operands: &[usize]) {
+        if self.function().add_instruction(op, operands) {
+            return;
+        }
+
+        let overflow = op
+            .operands()
+            .iter()
+            .zip(operands)
+            .find(|(operand, value)| !operand.fits(**value, true))
+            .map(|(operand, _)| *operand);
+        let message = match (overflow, op) {
+            (Some(Operand::Short), _) => "Too many constants in one function",
+            (_, OpCode::BuildList) => "Too many elements in list literal",
+            (_, OpCode::BuildMap) => "Too many entries in map literal",
+            (_, OpCode::GetCaptured | OpCode::SetCaptured) => "Too many captured variables",
+            (_, OpCode::GetLocal | OpCode::SetLocal) => "Too many local variables",
+            _ => "Too many arguments",
+        };
+        let span = self.function().current_span();
+        self.errors
+            .push(LoxError::new(message, ErrorContext::Compile, Some(span)));
+    }
+
     fn add_constant(&mut self, value: Value) {
-        let address = self.vm.add_constant(value);
-        self.function().add_op(OpCode::Constant);
-        self.function().add_address(address);
+        let address = self.function().add_constant(value);
+        self.add_instruction(OpCode::Constant, &[address]);
     }
 
-    fn new_function(&mut self, name: String, arity: u128) {
-        let function = Function::new(name, arity);
+    fn new_function(&mut self, name: String, arity: u128, span: Span) {
+        let mut function = Function::new(name, arity);
+        function.set_span(span);
         self.functions.push(function);
     }
 
-    fn new_loop(&mut self, name: String) {
-        let function = Function::new_loop(name);
-        self.functions.push(function);
+    fn add_upvalues(&mut self, span: Span, name: &str) {
+        for (is_local, index) in self.resolution.upvalues(span, name) {
+            self.function().add_upvalue(is_local, index);
+        }
     }
 }
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+
+use crate::function::Function;
+use crate::op::{OpCode, Operand};
+use crate::value::Value;
+
+pub(crate) fn disassemble(function: &Function, source: Option<&str>) -> String {
+    let mut output = String::new();
+    section(&mut output, "script".to_string(), function, source);
+    output
+}
+
+fn section(output: &mut String, label: String, function: &Function, source: Option<&str>) {
+    let _ = writeln!(output, "== {} ==", label);
+
+    let mut line = None;
+    let mut offset = 0;
+    while let Some((op, operands, next)) = function.instruction(offset) {
+        let span = function.span(offset).unwrap_or_default();
+        if line != Some(span.line()) {
+            line = Some(span.line());
+            let text = source
+                .and_then(|source| source.lines().nth(span.line().checked_sub(1)?))
+                .map(str::trim)
+                .unwrap_or_default();
+            let _ = writeln!(output, "{:>4} | {}", span.line(), text);
+        }
+
+        let mnemonic = match function.code(offset) == Some(OpCode::Wide as u8) {
+            true => format!("Wide {:?}", op),
+            false => format!("{:?}", op),
+        };
+        let mut text = format!("     {:0>4}  {}", offset, mnemonic);
+        if !operands.is_empty() {
+            let operands: Vec<String> = operands.iter().map(usize::to_string).collect();
+            let _ = write!(
+                text,
+                "{}{}",
+                " ".repeat(18usize.saturating_sub(mnemonic.len())),
+                operands.join(" ")
+            );
+        }
+
+        match op {
+            OpCode::Loop => {
+                let _ = write!(text, "  -> {:0>4}", next - operands[0]);
+            }
+            OpCode::Jump | OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let _ = write!(text, "  -> {:0>4}", next + operands[0]);
+            }
+            _ if op.operands().first() == Some(&Operand::Short) => {
+                if let Some(constant) = function.constant(operands[0]) {
+                    let _ = write!(text, "  ; {}", describe(constant));
+                }
+            }
+            _ => {}
+        }
+        let _ = writeln!(output, "{}", text);
+
+        offset = next;
+    }
+
+    for constant in function.constants() {
+        match constant {
+            Value::Function(closure) => {
+                let nested = closure.function();
+                let _ = writeln!(output);
+                section(output, nested.to_string(), &nested, source);
+            }
+            Value::Class(class) => {
+                let _ = writeln!(output);
+                section(
+                    output,
+                    format!("{} fields", class.name()),
+                    &class.initializer(),
+                    source,
+                );
+                for (method, _) in class.methods() {
+                    let _ = writeln!(output);
+                    section(
+                        output,
+                        format!("{}.{}", class.name(), method),
+                        &method,
+                        source,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn describe(constant: &Value) -> String {
+    match constant {
+        Value::String(value) => format!("{:?}", value),
+        Value::Function(closure) => format!("<fun {}>", closure),
+        Value::Class(class) => format!("<class {}>", class),
+        value => String::from(value.clone()),
+    }
+}
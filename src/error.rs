@@ -2,40 +2,123 @@ use std::error::Error;
 use std::fmt;
 use std::process::{ExitCode, Termination};
 
+use crate::token::Span;
+
 #[derive(Debug)]
 pub(crate) enum ErrorContext {
     Compile,
+    Runtime,
+    Bytecode,
+    Warning,
 }
 
 #[derive(Debug)]
 pub(crate) struct LoxError {
     message: String,
-    line: Option<usize>,
+    span: Option<Span>,
     context: ErrorContext,
+    help: Option<String>,
+    trace: Vec<String>,
 }
 
 impl LoxError {
-    pub(crate) fn new(msg: &str, context: ErrorContext, line: Option<usize>) -> LoxError {
+    pub(crate) fn new(msg: &str, context: ErrorContext, span: Option<Span>) -> LoxError {
         LoxError {
-            line,
+            span,
             context,
+            help: None,
+            trace: vec![],
             message: msg.to_string(),
         }
     }
+
+    pub(crate) fn with_help(mut self, help: &str) -> LoxError {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub(crate) fn with_span(mut self, span: Option<Span>) -> LoxError {
+        self.span = self.span.or(span);
+        self
+    }
+
+    pub(crate) fn with_trace(mut self, trace: Vec<String>) -> LoxError {
+        self.trace = trace;
+        self
+    }
+
+    pub(crate) fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub(crate) fn render(&self, file: &str, source: &str) -> String {
+        let mut report = format!("{}: {}", self.title(), self.message);
+        let gutter = match self.span {
+            Some(span) => " ".repeat(span.line().to_string().len()),
+            None => String::new(),
+        };
+
+        if let Some(span) = self.span {
+            report.push_str(format!("\n{}--> {}:{}", gutter, file, span).as_str());
+
+            let text = span
+                .line()
+                .checked_sub(1)
+                .and_then(|index| source.lines().nth(index));
+            if let Some(text) = text {
+                let indent: String = text
+                    .chars()
+                    .take(span.column().saturating_sub(1))
+                    .map(|character| match character {
+                        '\t' => '\t',
+                        _ => ' ',
+                    })
+                    .collect();
+                let width = match span.end() {
+                    (line, column) if line == span.line() && column > span.column() => {
+                        column - span.column()
+                    }
+                    _ => 1,
+                };
+
+                report.push_str(format!("\n{} |", gutter).as_str());
+                report.push_str(format!("\n{} | {}", span.line(), text).as_str());
+                report.push_str(format!("\n{} | {}{}", gutter, indent, "^".repeat(width)).as_str());
+            }
+        }
+
+        if let Some(help) = &self.help {
+            report.push_str(format!("\n{} = help: {}", gutter, help).as_str());
+        }
+
+        if let Some((first, rest)) = self.trace.split_first() {
+            report.push_str(format!("\n{} = trace: {}", gutter, first).as_str());
+            rest.iter().for_each(|entry| {
+                report.push_str(format!("\n{}          {}", gutter, entry).as_str())
+            });
+        }
+
+        report
+    }
+
+    fn title(&self) -> String {
+        match self.context {
+            ErrorContext::Warning => "Warning".to_string(),
+            _ => format!("{:?} error", self.context),
+        }
+    }
 }
 
 impl fmt::Display for LoxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.line {
-            None => write!(f, "{:?} error: {}", self.context, self.message),
-            Some(line) => {
-                write!(
-                    f,
-                    "{:?} error: {} at line {}",
-                    self.context, self.message, line
-                )
-            }
+        match self.span {
+            None => write!(f, "{}: {}", self.title(), self.message)?,
+            Some(span) => write!(f, "{}: {} at {}", self.title(), self.message, span)?,
         }
+
+        self.trace
+            .iter()
+            .try_for_each(|entry| write!(f, "\n    {}", entry))
     }
 }
 
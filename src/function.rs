@@ -1,18 +1,50 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
+use std::rc::Rc;
 
-use crate::chunk::{Chunk, ChunkIterator};
-use crate::op::OpCode;
+use num_bigint::BigInt;
+
+use crate::chunk::Chunk;
+use crate::disassembler::disassemble;
+use crate::op::{OpCode, Operand};
+use crate::token::Span;
 use crate::value::Value;
 
 #[derive(Clone)]
 pub(crate) struct Function {
+    span: Span,
     arity: u128,
     name: String,
-    is_loop: bool,
-    codes: Chunk<usize>,
-    has_return: Option<bool>,
-    captures: HashMap<String, (usize, usize, Option<Value>)>,
+    spans: Vec<Span>,
+    codes: Vec<u8>,
+    constants: Chunk<Value>,
+    interned: HashMap<ConstantKey, usize>,
+    upvalues: Vec<(bool, usize)>,
+}
+
+// constants are interned bit-exactly, so 0.0, -0.0 and NaN stay distinct
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Boolean(bool),
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Number(u64),
+    String(String),
+}
+
+impl ConstantKey {
+    fn from(value: &Value) -> Option<ConstantKey> {
+        match value {
+            Value::Nil => Some(ConstantKey::Nil),
+            Value::Boolean(value) => Some(ConstantKey::Boolean(*value)),
+            Value::Int(value) => Some(ConstantKey::Int(*value)),
+            Value::BigInt(value) => Some(ConstantKey::BigInt(value.clone())),
+            Value::Number(value) => Some(ConstantKey::Number(value.to_bits())),
+            Value::String(value) => Some(ConstantKey::String(value.clone())),
+            _ => None,
+        }
+    }
 }
 
 impl Function {
@@ -20,10 +52,12 @@ impl Function {
         Function {
             name,
             arity,
-            is_loop: false,
-            codes: Chunk::new(),
-            has_return: Some(false),
-            captures: HashMap::new(),
+            span: Span::default(),
+            spans: vec![],
+            codes: vec![],
+            constants: Chunk::new(),
+            interned: HashMap::new(),
+            upvalues: vec![],
         }
     }
 
@@ -31,21 +65,12 @@ impl Function {
         Function {
             name,
             arity: 0,
-            is_loop: false,
-            has_return: None,
-            codes: Chunk::new(),
-            captures: HashMap::new(),
-        }
-    }
-
-    pub(crate) fn new_loop(name: String) -> Function {
-        Function {
-            name,
-            arity: 0,
-            is_loop: true,
-            codes: Chunk::new(),
-            has_return: Some(false),
-            captures: HashMap::new(),
+            span: Span::default(),
+            spans: vec![],
+            codes: vec![],
+            constants: Chunk::new(),
+            interned: HashMap::new(),
+            upvalues: vec![],
         }
     }
 
@@ -57,79 +82,186 @@ impl Function {
         self.arity
     }
 
+    pub(crate) fn current_span(&self) -> Span {
+        self.span
+    }
+
+    pub(crate) fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    pub(crate) fn span(&self, offset: usize) -> Option<Span> {
+        self.spans.get(offset).copied()
+    }
+
     pub(crate) fn add_op(&mut self, op: OpCode) {
-        self.codes.add(op as usize);
+        self.add_instruction(op, &[]);
+    }
+
+    pub(crate) fn add_instruction(&mut self, op: OpCode, operands: &[usize]) -> bool {
+        let layout = op.operands();
+        if !layout
+            .iter()
+            .zip(operands)
+            .all(|(operand, value)| operand.fits(*value, true))
+        {
+            return false;
+        }
+
+        let wide = !layout
+            .iter()
+            .zip(operands)
+            .all(|(operand, value)| operand.fits(*value, false));
+
+        if wide {
+            self.add_code(OpCode::Wide as u8);
+        }
+        self.add_code(op as u8);
+        for (operand, value) in layout.iter().zip(operands) {
+            self.add_operand(*value, operand.width(wide));
+        }
+        true
     }
 
+    // forward jumps are emitted wide and narrowed once the function is complete
     pub(crate) fn add_jump(&mut self, if_false: bool) -> usize {
+        self.add_code(OpCode::Wide as u8);
         match if_false {
-            true => self.codes.add(OpCode::JumpIfFalse as usize),
-            false => self.codes.add(OpCode::Jump as usize),
+            true => self.add_code(OpCode::JumpIfFalse as u8),
+            false => self.add_code(OpCode::Jump as u8),
         };
-        self.codes.add(OpCode::Invalid as usize)
+        self.add_operand(u32::MAX as usize, 4);
+        self.codes.len() - 4
     }
 
-    pub(crate) fn patch_jump(&mut self, address: usize) {
-        self.codes.set(address, self.codes.size() - address - 1);
+    pub(crate) fn patch_jump(&mut self, address: usize) -> bool {
+        let distance = self.codes.len() - address - 4;
+        if !Operand::Short.fits(distance, true) {
+            return false;
+        }
+        self.codes[address..address + 4].copy_from_slice(&(distance as u32).to_be_bytes());
+        true
     }
 
-    pub(crate) fn add_address(&mut self, address: usize) {
-        self.codes.add(address);
+    pub(crate) fn add_loop(&mut self, start: usize) -> bool {
+        let distance = match self.codes.len() + 3 - start {
+            distance if Operand::Short.fits(distance, false) => distance,
+            _ => self.codes.len() + 6 - start,
+        };
+        if !Operand::Short.fits(distance, true) {
+            return false;
+        }
+        self.add_instruction(OpCode::Loop, &[distance]);
+        true
     }
 
-    pub(crate) fn has_return(&self) -> Option<bool> {
-        self.has_return
+    pub(crate) fn code(&self, offset: usize) -> Option<u8> {
+        self.codes.get(offset).copied()
     }
 
-    pub(crate) fn already_returns(&mut self) {
-        self.has_return = Some(true);
+    pub(crate) fn size(&self) -> usize {
+        self.codes.len()
     }
 
-    pub(crate) fn is_loop(&self) -> bool {
-        self.is_loop
+    pub(crate) fn instruction(&self, offset: usize) -> Option<(OpCode, Vec<usize>, usize)> {
+        let mut op = OpCode::from(self.code(offset)?);
+        let mut offset = offset + 1;
+        let wide = op == OpCode::Wide;
+        if wide {
+            op = OpCode::from(self.code(offset)?);
+            offset += 1;
+        }
+
+        let mut operands = vec![];
+        for operand in op.operands() {
+            let width = operand.width(wide);
+            let bytes = self.codes.get(offset..offset + width)?;
+            operands.push(
+                bytes
+                    .iter()
+                    .fold(0, |value, byte| (value << 8) | *byte as usize),
+            );
+            offset += width;
+        }
+
+        Some((op, operands, offset))
     }
 
-    pub(crate) fn captures(&self) -> HashMap<String, (usize, usize, Option<Value>)> {
-        self.captures.clone()
+    pub(crate) fn add_constant(&mut self, constant: Value) -> usize {
+        let Some(key) = ConstantKey::from(&constant) else {
+            return self.constants.add(constant);
+        };
+        match self.interned.get(&key) {
+            Some(address) => *address,
+            None => {
+                let address = self.constants.add(constant);
+                self.interned.insert(key, address);
+                address
+            }
+        }
     }
 
-    pub(crate) fn add_capture(&mut self, name: String, frame: usize, address: usize) {
-        self.captures.insert(name, (frame, address, None));
+    pub(crate) fn constant(&self, address: usize) -> Option<&Value> {
+        self.constants.get(address)
     }
 
-    pub(crate) fn populate_capture(&mut self, name: String, value: Value) {
-        if let Some((frame, address, _)) = self.captures.get(&name) {
-            self.captures.insert(name, (*frame, *address, Some(value)));
-        }
+    pub(crate) fn codes(&self) -> &[u8] {
+        &self.codes
     }
 
-    pub(crate) fn get_capture(&self, name: String) -> Option<Value> {
-        self.captures
-            .get(&name)
-            .and_then(|(_, _, value)| value.clone())
+    pub(crate) fn constants(&self) -> &Chunk<Value> {
+        &self.constants
     }
-}
 
-pub(crate) struct FunctionIterator<'a> {
-    iterator: ChunkIterator<'a, usize>,
-}
+    pub(crate) fn set_code(&mut self, codes: Vec<u8>, spans: Vec<Span>) {
+        self.codes = codes;
+        self.spans = spans;
+    }
 
-impl<'a> IntoIterator for &'a Function {
-    type Item = usize;
-    type IntoIter = FunctionIterator<'a>;
+    pub(crate) fn set_constants(&mut self, constants: Vec<Value>) {
+        self.constants = Chunk::new();
+        self.interned.clear();
+        constants.into_iter().for_each(|constant| {
+            let key = ConstantKey::from(&constant);
+            let address = self.constants.add(constant);
+            if let Some(key) = key {
+                self.interned.entry(key).or_insert(address);
+            }
+        });
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        FunctionIterator {
-            iterator: self.codes.into_iter(),
+    pub(crate) fn clear_code(&mut self) {
+        self.codes.clear();
+        self.spans.clear();
+    }
+
+    fn add_operand(&mut self, value: usize, width: usize) {
+        for shift in (0..width).rev() {
+            self.add_code((value >> (8 * shift)) as u8);
         }
     }
-}
 
-impl<'a> Iterator for FunctionIterator<'a> {
-    type Item = usize;
+    fn add_code(&mut self, code: u8) {
+        self.spans.push(self.span);
+        self.codes.push(code);
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iterator.next().copied()
+    pub(crate) fn upvalues(&self) -> Vec<(bool, usize)> {
+        self.upvalues.clone()
+    }
+
+    pub(crate) fn add_upvalue(&mut self, is_local: bool, index: usize) -> usize {
+        match self
+            .upvalues
+            .iter()
+            .position(|upvalue| *upvalue == (is_local, index))
+        {
+            Some(address) => address,
+            None => {
+                self.upvalues.push((is_local, index));
+                self.upvalues.len() - 1
+            }
+        }
     }
 }
 
@@ -141,22 +273,6 @@ impl Display for Function {
 
 impl Debug for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut iterator = self.codes.into_iter().peekable().enumerate();
-        while let Some((offset, current)) = iterator.next() {
-            let op_code = OpCode::from(*current as u8);
-            let string_offset = format!("{:0>4}", offset);
-            // writeln!(f, "{}   {:?}", string_offset, op_code)?;
-            let params = OpCode::params(&op_code);
-            for _ in 0..params {
-                iterator.next();
-                // let Some((offset, address)) = iterator.next() else {
-                //     todo!()
-                // };
-                // let string_offset = format!("{:0>4}", offset);
-                // writeln!(f, "{}   {:?}", string_offset, address)?;
-            }
-            writeln!(f, "{}   {:?}", string_offset, op_code)?;
-        }
-        Ok(())
+        write!(f, "{}", disassemble(self, None))
     }
 }
@@ -1,16 +1,29 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::Path;
 
+mod arithmetic;
+mod ast;
+mod bytecode;
 mod chunk;
+mod class;
+mod closure;
 mod compiler;
+mod disassembler;
 mod error;
 mod function;
+mod map;
 mod nif;
 mod op;
+mod optimizer;
+mod parser;
+mod peephole;
+mod resolver;
 mod scanner;
 mod tests;
 mod token;
+mod trace;
 mod value;
 mod vm;
 
@@ -20,10 +33,50 @@ use rustyline::DefaultEditor;
 
 fn main() -> Result<InterpretResult, InterpretResult> {
     let args: Vec<String> = env::args().collect();
+    let (flags, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|arg| arg.starts_with("--"));
 
     let mut vm = vm::VM::new();
+    let mut level = None;
+    let mut trace_file = None;
+    let mut trace_function = None;
+    for flag in flags {
+        match flag.split_once('=') {
+            None if flag == "--plain" => vm.set_plain_diagnostics(true),
+            None if flag == "--no-optimize" => vm.set_optimize(false),
+            None if flag == "--trace" => level = Some(trace::TraceLevel::Ops),
+            Some(("--trace", value)) => match trace::TraceLevel::parse(value) {
+                Some(value) => level = Some(value),
+                None => return Err(InterpretResult::CliError),
+            },
+            Some(("--trace-file", path)) => trace_file = Some(path),
+            Some(("--trace-fn", name)) => trace_function = Some(name),
+            _ => return Err(InterpretResult::CliError),
+        }
+    }
+
+    if let Some(level) = level {
+        let mut tracer = trace::Tracer::new(level);
+        if let Some(path) = trace_file {
+            let Ok(file_tracer) = tracer.write_to(path) else {
+                return Err(InterpretResult::CliError);
+            };
+            tracer = file_tracer;
+        }
+        if let Some(name) = trace_function {
+            tracer = tracer.only(name);
+        }
+        vm.set_tracer(Some(tracer));
+    }
+
     match &args[..] {
         [_] => repl(&mut vm),
+        [_, command, path] if *command == "compile" => compile_file(&mut vm, path, None),
+        [_, command, path, flag, output] if *command == "compile" && *flag == "-o" => {
+            compile_file(&mut vm, path, Some(output))
+        }
+        [_, command, path] if *command == "disasm" => disassemble_file(&mut vm, path),
+        [_, path] if path.ends_with(".loxc") => run_bytecode(&mut vm, path),
         [_, path] => run_file(&mut vm, path),
         _ => Err(InterpretResult::CliError),
         // _ => error::error_out(error::LoxError::new(
@@ -35,6 +88,7 @@ fn main() -> Result<InterpretResult, InterpretResult> {
 }
 
 fn repl(vm: &mut vm::VM) -> Result<InterpretResult, InterpretResult> {
+    vm.set_file("<repl>");
     match DefaultEditor::new() {
         Ok(mut rl) => {
             loop {
@@ -99,6 +153,7 @@ fn repl(vm: &mut vm::VM) -> Result<InterpretResult, InterpretResult> {
 // }
 
 fn run_file(vm: &mut vm::VM, path: &String) -> Result<InterpretResult, InterpretResult> {
+    vm.set_file(path);
     match File::open(path) {
         Ok(mut file) => {
             let mut script = String::new();
@@ -113,3 +168,68 @@ fn run_file(vm: &mut vm::VM, path: &String) -> Result<InterpretResult, Interpret
         Err(_error) => Err(InterpretResult::CliError),
     }
 }
+
+fn compile_file(
+    vm: &mut vm::VM,
+    path: &String,
+    output: Option<&String>,
+) -> Result<InterpretResult, InterpretResult> {
+    vm.set_file(path);
+    let Ok(script) = fs::read_to_string(path) else {
+        return Err(InterpretResult::CliError);
+    };
+    let function = vm.compile(script)?;
+
+    let output = match output {
+        Some(output) => Path::new(output).to_path_buf(),
+        None => Path::new(path).with_extension("loxc"),
+    };
+    match fs::write(output, bytecode::serialize(&function)) {
+        Ok(_) => Ok(InterpretResult::Ok),
+        Err(_error) => Err(InterpretResult::CliError),
+    }
+}
+
+fn run_bytecode(vm: &mut vm::VM, path: &String) -> Result<InterpretResult, InterpretResult> {
+    vm.set_file(path);
+    let Ok(bytes) = fs::read(path) else {
+        return Err(InterpretResult::CliError);
+    };
+
+    match bytecode::deserialize(&bytes) {
+        Ok(function) => match vm.run(function) {
+            InterpretResult::Ok => Ok(InterpretResult::Ok),
+            _result => Err(InterpretResult::RuntimeError),
+        },
+        Err(error) => {
+            vm.report(&error);
+            Err(InterpretResult::CliError)
+        }
+    }
+}
+
+fn disassemble_file(vm: &mut vm::VM, path: &String) -> Result<InterpretResult, InterpretResult> {
+    vm.set_file(path);
+    if path.ends_with(".loxc") {
+        let Ok(bytes) = fs::read(path) else {
+            return Err(InterpretResult::CliError);
+        };
+        return match bytecode::deserialize(&bytes) {
+            Ok(function) => {
+                print!("{}", disassembler::disassemble(&function, None));
+                Ok(InterpretResult::Ok)
+            }
+            Err(error) => {
+                vm.report(&error);
+                Err(InterpretResult::CliError)
+            }
+        };
+    }
+
+    let Ok(script) = fs::read_to_string(path) else {
+        return Err(InterpretResult::CliError);
+    };
+    let function = vm.compile(script.clone())?;
+    print!("{}", disassembler::disassemble(&function, Some(&script)));
+    Ok(InterpretResult::Ok)
+}
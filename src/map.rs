@@ -0,0 +1,131 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::value::Value;
+
+// unlike == in the language, map keys treat NaN as equal to itself
+trait AsKey {
+    fn value(&self) -> &Value;
+}
+
+impl AsKey for Value {
+    fn value(&self) -> &Value {
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Key(Value);
+
+impl AsKey for Key {
+    fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl<'a> Borrow<dyn AsKey + 'a> for Key {
+    fn borrow(&self) -> &(dyn AsKey + 'a) {
+        self
+    }
+}
+
+impl PartialEq for dyn AsKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.value(), other.value()) {
+            (Value::Number(left), Value::Number(right)) if left.is_nan() => right.is_nan(),
+            (left, right) => left == right,
+        }
+    }
+}
+
+impl Eq for dyn AsKey + '_ {}
+
+impl Hash for dyn AsKey + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value().hash(state)
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        (self as &dyn AsKey) == (other as &dyn AsKey)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as &dyn AsKey).hash(state)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Map {
+    // removed entries leave a tombstone until compaction, keeping insertion order
+    entries: Vec<Option<(Value, Value)>>,
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub(crate) fn new() -> Map {
+        Map::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub(crate) fn get(&self, key: &Value) -> Option<&Value> {
+        self.index
+            .get(key as &dyn AsKey)
+            .and_then(|position| self.entries[*position].as_ref())
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn contains(&self, key: &Value) -> bool {
+        self.index.contains_key(key as &dyn AsKey)
+    }
+
+    pub(crate) fn insert(&mut self, key: Value, value: Value) {
+        // -0 and 0 are the same key, store them as 0
+        let key = match key {
+            Value::Number(number) => Value::Number(number + 0.0),
+            key => key,
+        };
+        match self.index.get(&key as &dyn AsKey) {
+            Some(position) => self.entries[*position] = Some((key, value)),
+            None => {
+                self.index.insert(Key(key.clone()), self.entries.len());
+                self.entries.push(Some((key, value)));
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &Value) -> Option<Value> {
+        let position = self.index.remove(key as &dyn AsKey)?;
+        let (_, value) = self.entries[position].take()?;
+        if self.entries.len() > 2 * self.index.len() {
+            self.compact();
+        }
+        Some(value)
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter().flatten()
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(Option::is_some);
+        for (position, (key, _)) in self.entries.iter().flatten().enumerate() {
+            if let Some(index) = self.index.get_mut(key as &dyn AsKey) {
+                *index = position;
+            }
+        }
+    }
+}
@@ -1,39 +1,85 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::rc::Rc;
 use std::time::Instant;
 
-use crate::error::InterpretResult;
-use crate::value::Value;
+use num_bigint::BigInt;
+use num_traits::Signed;
+
+use crate::arithmetic::floor_divide;
+use crate::error::{ErrorContext, LoxError};
+use crate::map::Map;
+use crate::value::{list_index, Value};
 use crate::vm::VM;
 
 pub(crate) trait Nif {
     fn name(&self) -> String;
     fn arity(&self) -> Option<u128>;
-    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), InterpretResult>;
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError>;
+}
+
+pub(crate) fn resolve_nif(name: &str) -> Option<Rc<dyn Nif>> {
+    nifs().into_iter().find(|nif| nif.name() == name)
 }
 
-pub(crate) fn resolve_nif(name: &str) -> Option<Box<dyn Nif>> {
-    match name {
-        "div" => Some(Box::new(Div)),
-        "clock" => Some(Box::new(Clock)),
-        "parse" => Some(Box::new(Parse)),
-        "print" => Some(Box::new(Print)),
-        "is_nil" => Some(Box::new(IsNil)),
-        "type_of" => Some(Box::new(TypeOf)),
-        "println" => Some(Box::new(PrintLn)),
-        "is_number" => Some(Box::new(IsNumber)),
-        "is_string" => Some(Box::new(IsString)),
-        "is_boolean" => Some(Box::new(IsBoolean)),
-        "is_function" => Some(Box::new(IsFunction)),
-        _ => None,
+pub(crate) fn nifs() -> Vec<Rc<dyn Nif>> {
+    vec![
+        Rc::new(Div),
+        Rc::new(Has),
+        Rc::new(Len),
+        Rc::new(Pop),
+        Rc::new(Push),
+        Rc::new(Keys),
+        Rc::new(Clock),
+        Rc::new(Parse),
+        Rc::new(Print),
+        Rc::new(Slice),
+        Rc::new(IsNil),
+        Rc::new(Delete),
+        Rc::new(Insert),
+        Rc::new(Remove),
+        Rc::new(Values),
+        Rc::new(TypeOf),
+        Rc::new(PrintLn),
+        Rc::new(Compare),
+        Rc::new(Entries),
+        Rc::new(Reverse),
+        Rc::new(Contains),
+        Rc::new(IsNumber),
+        Rc::new(IsString),
+        Rc::new(IsBoolean),
+        Rc::new(IsFunction),
+    ]
+}
+
+impl Debug for dyn Nif {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
     }
 }
 
 struct Div;
+struct Has;
+struct Len;
+struct Pop;
+struct Push;
+struct Keys;
 struct Clock;
 struct Parse;
 struct Print;
+struct Slice;
 struct IsNil;
+struct Delete;
+struct Insert;
+struct Remove;
+struct Values;
 struct TypeOf;
 struct PrintLn;
+struct Compare;
+struct Entries;
+struct Reverse;
+struct Contains;
 struct IsNumber;
 struct IsString;
 struct IsBoolean;
@@ -48,19 +94,22 @@ impl Nif for Div {
         Some(2)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let right = vm.stack_pop().unwrap();
         let left = vm.stack_pop().unwrap();
 
-        match (left.clone(), right.clone()) {
-            (Value::Number(_), Value::Number(_)) => {
-                let left: i128 = left.into();
-                let right: i128 = right.into();
-                vm.stack_push(Value::Number((left / right) as f64));
-                Ok(())
+        let result = match (&left, &right) {
+            (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+                floor_divide(&left, &right)
             }
-            _ => Err(InterpretResult::RuntimeError),
-        }
+            _ => Err(format!(
+                "div expects two ints, got {} and {}",
+                left.type_name(),
+                right.type_name()
+            )),
+        };
+        vm.stack_push(result.map_err(|message| runtime_error(&message))?);
+        Ok(())
     }
 }
 
@@ -73,12 +122,12 @@ impl Nif for Clock {
         Some(0)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let start_time = vm.start_time();
         let now = Instant::now();
         let elapsed = now.duration_since(start_time);
         let elapsed = elapsed.as_nanos();
-        vm.stack_push(Value::Number(elapsed as f64));
+        vm.stack_push(Value::Int(elapsed as i64));
         Ok(())
     }
 }
@@ -92,7 +141,7 @@ impl Nif for Parse {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
 
         let result = match arg {
@@ -101,6 +150,12 @@ impl Nif for Parse {
             Value::String(value) if value.as_str().to_lowercase() == "false" => {
                 Value::Boolean(false)
             }
+            Value::String(value) if value.parse::<i64>().is_ok() => {
+                Value::Int(value.parse::<i64>().unwrap())
+            }
+            Value::String(value) if is_integer(&value) => {
+                Value::from(value.parse::<BigInt>().unwrap())
+            }
             Value::String(value) if value.parse::<f64>().is_ok() => {
                 Value::Number(value.parse::<f64>().unwrap())
             }
@@ -122,7 +177,7 @@ impl Nif for Print {
     }
 
     #[cfg(not(test))]
-    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError> {
         let mut args = vec![];
 
         for _ in 0..args_count {
@@ -137,11 +192,12 @@ impl Nif for Print {
             })
             .for_each(|item| print!("{}", item));
 
+        vm.stack_push(Value::Nil);
         Ok(())
     }
 
     #[cfg(test)]
-    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError> {
         let mut args = vec![];
 
         for _ in 0..args_count {
@@ -156,6 +212,7 @@ impl Nif for Print {
             })
             .for_each(|item| vm.get_stdout().push(item));
 
+        vm.stack_push(Value::Nil);
         Ok(())
     }
 }
@@ -169,7 +226,7 @@ impl Nif for IsNil {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
         vm.stack_push(Value::Boolean(matches!(arg, Value::Nil)));
         Ok(())
@@ -185,16 +242,10 @@ impl Nif for TypeOf {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
 
-        let value_type = match arg {
-            Value::Nil => "nil",
-            Value::String(_) => "string",
-            Value::Number(_) => "number",
-            Value::Boolean(_) => "boolean",
-            Value::Function(_) => "function",
-        };
+        let value_type = arg.type_name();
 
         vm.stack_push(Value::String(value_type.into()));
         Ok(())
@@ -211,14 +262,14 @@ impl Nif for PrintLn {
     }
 
     #[cfg(not(test))]
-    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError> {
         let _ = Print.call(vm, args_count);
         println!();
         Ok(())
     }
 
     #[cfg(test)]
-    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError> {
         let _ = Print.call(vm, args_count);
         vm.get_stdout().push("\n".to_string());
         Ok(())
@@ -234,9 +285,12 @@ impl Nif for IsNumber {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
-        vm.stack_push(Value::Boolean(matches!(arg, Value::Number(_))));
+        vm.stack_push(Value::Boolean(matches!(
+            arg,
+            Value::Int(_) | Value::BigInt(_) | Value::Number(_)
+        )));
         Ok(())
     }
 }
@@ -250,7 +304,7 @@ impl Nif for IsString {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
         vm.stack_push(Value::Boolean(matches!(arg, Value::String(_))));
         Ok(())
@@ -266,7 +320,7 @@ impl Nif for IsBoolean {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
         vm.stack_push(Value::Boolean(matches!(arg, Value::Boolean(_))));
         Ok(())
@@ -282,9 +336,371 @@ impl Nif for IsFunction {
         Some(1)
     }
 
-    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), InterpretResult> {
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
         let arg = vm.stack_pop().unwrap();
-        vm.stack_push(Value::Boolean(matches!(arg, Value::Function(_))));
+        vm.stack_push(Value::Boolean(matches!(
+            arg,
+            Value::Function(_) | Value::BoundMethod(_) | Value::Nif(_)
+        )));
+        Ok(())
+    }
+}
+
+impl Nif for Len {
+    fn name(&self) -> String {
+        "len".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let length = match vm.stack_pop().unwrap() {
+            Value::List(list) => list.borrow().len(),
+            Value::Map(map) => map.borrow().len(),
+            Value::String(value) => value.chars().count(),
+            value => {
+                return Err(runtime_error(
+                    format!(
+                        "len expects a list, a map or a string, got {}",
+                        value.type_name()
+                    )
+                    .as_str(),
+                ))
+            }
+        };
+        vm.stack_push(Value::Int(length as i64));
+        Ok(())
+    }
+}
+
+impl Nif for Pop {
+    fn name(&self) -> String {
+        "pop".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let list = list_arg("pop", vm.stack_pop().unwrap())?;
+        let Some(value) = list.borrow_mut().pop() else {
+            return Err(runtime_error("pop from an empty list"));
+        };
+        vm.stack_push(value);
         Ok(())
     }
 }
+
+impl Nif for Push {
+    fn name(&self) -> String {
+        "push".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let value = vm.stack_pop().unwrap();
+        let list = list_arg("push", vm.stack_pop().unwrap())?;
+        list.borrow_mut().push(value);
+        vm.stack_push(Value::Nil);
+        Ok(())
+    }
+}
+
+impl Nif for Slice {
+    fn name(&self) -> String {
+        "slice".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        None
+    }
+
+    fn call(&self, vm: &mut VM, args_count: usize) -> Result<(), LoxError> {
+        if !(2..=3).contains(&args_count) {
+            for _ in 0..args_count {
+                vm.stack_pop();
+            }
+            return Err(runtime_error(
+                format!("slice expects 2 or 3 arguments but got {}", args_count).as_str(),
+            ));
+        }
+
+        let end = match args_count {
+            3 => Some(vm.stack_pop().unwrap()),
+            _ => None,
+        };
+        let start = vm.stack_pop().unwrap();
+        let list = list_arg("slice", vm.stack_pop().unwrap())?;
+
+        let length = list.borrow().len();
+        let start = slice_bound(&start, length)?;
+        let end = match end {
+            Some(end) => slice_bound(&end, length)?,
+            None => length,
+        };
+
+        let elements = list.borrow()[start..end.max(start)].to_vec();
+        vm.stack_push(Value::List(Rc::new(RefCell::new(elements))));
+        Ok(())
+    }
+}
+
+impl Nif for Insert {
+    fn name(&self) -> String {
+        "insert".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(3)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let value = vm.stack_pop().unwrap();
+        let index = vm.stack_pop().unwrap();
+        let list = list_arg("insert", vm.stack_pop().unwrap())?;
+
+        let length = list.borrow().len();
+        let position = list_index(&index, length + 1).map_err(|message| runtime_error(&message))?;
+        list.borrow_mut().insert(position, value);
+        vm.stack_push(Value::Nil);
+        Ok(())
+    }
+}
+
+impl Nif for Remove {
+    fn name(&self) -> String {
+        "remove".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let index = vm.stack_pop().unwrap();
+        let list = list_arg("remove", vm.stack_pop().unwrap())?;
+
+        let length = list.borrow().len();
+        let position = list_index(&index, length).map_err(|message| runtime_error(&message))?;
+        let value = list.borrow_mut().remove(position);
+        vm.stack_push(value);
+        Ok(())
+    }
+}
+
+impl Nif for Reverse {
+    fn name(&self) -> String {
+        "reverse".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let list = list_arg("reverse", vm.stack_pop().unwrap())?;
+        list.borrow_mut().reverse();
+        vm.stack_push(Value::Nil);
+        Ok(())
+    }
+}
+
+impl Nif for Contains {
+    fn name(&self) -> String {
+        "contains".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let value = vm.stack_pop().unwrap();
+        let list = list_arg("contains", vm.stack_pop().unwrap())?;
+        let found = list.borrow().contains(&value);
+        vm.stack_push(Value::Boolean(found));
+        Ok(())
+    }
+}
+
+impl Nif for Has {
+    fn name(&self) -> String {
+        "has".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let key = key_arg(vm.stack_pop().unwrap())?;
+        let map = map_arg("has", vm.stack_pop().unwrap())?;
+        let found = map.borrow().contains(&key);
+        vm.stack_push(Value::Boolean(found));
+        Ok(())
+    }
+}
+
+impl Nif for Keys {
+    fn name(&self) -> String {
+        "keys".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let map = map_arg("keys", vm.stack_pop().unwrap())?;
+        let keys = map.borrow().entries().map(|(key, _)| key.clone()).collect();
+        vm.stack_push(Value::List(Rc::new(RefCell::new(keys))));
+        Ok(())
+    }
+}
+
+impl Nif for Delete {
+    fn name(&self) -> String {
+        "delete".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let key = key_arg(vm.stack_pop().unwrap())?;
+        let map = map_arg("delete", vm.stack_pop().unwrap())?;
+        let value = map.borrow_mut().remove(&key);
+        vm.stack_push(value.unwrap_or(Value::Nil));
+        Ok(())
+    }
+}
+
+impl Nif for Values {
+    fn name(&self) -> String {
+        "values".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let map = map_arg("values", vm.stack_pop().unwrap())?;
+        let values = map
+            .borrow()
+            .entries()
+            .map(|(_, value)| value.clone())
+            .collect();
+        vm.stack_push(Value::List(Rc::new(RefCell::new(values))));
+        Ok(())
+    }
+}
+
+impl Nif for Compare {
+    fn name(&self) -> String {
+        "compare".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(2)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let right = vm.stack_pop().unwrap();
+        let left = vm.stack_pop().unwrap();
+
+        let ordering = match left.compare(&right) {
+            Some(Ordering::Less) => -1,
+            Some(Ordering::Equal) => 0,
+            Some(Ordering::Greater) => 1,
+            None => {
+                return Err(runtime_error(
+                    format!("compare cannot order two {} values", left.type_name()).as_str(),
+                ))
+            }
+        };
+        vm.stack_push(Value::Int(ordering));
+        Ok(())
+    }
+}
+
+impl Nif for Entries {
+    fn name(&self) -> String {
+        "entries".into()
+    }
+
+    fn arity(&self) -> Option<u128> {
+        Some(1)
+    }
+
+    fn call(&self, vm: &mut VM, _args_count: usize) -> Result<(), LoxError> {
+        let map = map_arg("entries", vm.stack_pop().unwrap())?;
+        let entries = map
+            .borrow()
+            .entries()
+            .map(|(key, value)| {
+                Value::List(Rc::new(RefCell::new(vec![key.clone(), value.clone()])))
+            })
+            .collect();
+        vm.stack_push(Value::List(Rc::new(RefCell::new(entries))));
+        Ok(())
+    }
+}
+
+fn list_arg(name: &str, value: Value) -> Result<Rc<RefCell<Vec<Value>>>, LoxError> {
+    match value {
+        Value::List(list) => Ok(list),
+        value => Err(runtime_error(
+            format!("{} expects a list, got {}", name, value.type_name()).as_str(),
+        )),
+    }
+}
+
+fn map_arg(name: &str, value: Value) -> Result<Rc<RefCell<Map>>, LoxError> {
+    match value {
+        Value::Map(map) => Ok(map),
+        value => Err(runtime_error(
+            format!("{} expects a map, got {}", name, value.type_name()).as_str(),
+        )),
+    }
+}
+
+fn key_arg(key: Value) -> Result<Value, LoxError> {
+    VM::check_key(&key).map_err(|message| runtime_error(&message))?;
+    Ok(key)
+}
+
+fn slice_bound(value: &Value, length: usize) -> Result<usize, LoxError> {
+    let bound = match value {
+        Value::Int(bound) => *bound,
+        Value::BigInt(bound) if bound.is_negative() => i64::MIN,
+        Value::BigInt(_) => i64::MAX,
+        _ => {
+            return Err(runtime_error(
+                format!("slice expects integer bounds, got {}", value.repr()).as_str(),
+            ))
+        }
+    };
+
+    let bound = match bound < 0 {
+        true => bound.saturating_add_unsigned(length as u64),
+        false => bound,
+    };
+    Ok(bound.clamp(0, length as i64) as usize)
+}
+
+fn is_integer(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|digit| digit.is_ascii_digit())
+}
+
+fn runtime_error(message: &str) -> LoxError {
+    LoxError::new(message, ErrorContext::Runtime, None)
+}
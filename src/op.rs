@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum OpCode {
     Add,
     Nil,
@@ -9,41 +9,97 @@ pub(crate) enum OpCode {
     Jump,
     Less,
     Loop,
+    Wide,
+    Class,
     Equal,
     Concat,
     Divide,
+    Invoke,
     Negate,
     Return,
     Greater,
+    BuildMap,
+    GetIndex,
+    GetSuper,
     GetLocal,
     Constant,
     Multiply,
     NotEqual,
+    SetIndex,
     SetLocal,
+    Subtract,
+    BuildList,
     DefGlobal,
     GetGlobal,
     LessEqual,
     SetGlobal,
+    JumpIfTrue,
+    AddConstant,
+    FloorDivide,
     JumpIfFalse,
     GetCaptured,
+    GetProperty,
+    InvokeSuper,
     MakeClosure,
+    SetProperty,
+    SetCaptured,
+    SetLocalPop,
     GreaterEqual,
+    CloseCaptured,
 
     Invalid,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Operand {
+    Byte,
+    Short,
+}
+
+impl Operand {
+    pub(crate) fn width(&self, wide: bool) -> usize {
+        let width = match self {
+            Self::Byte => 1,
+            Self::Short => 2,
+        };
+        match wide {
+            true => width * 2,
+            false => width,
+        }
+    }
+
+    pub(crate) fn fits(&self, value: usize, wide: bool) -> bool {
+        value < 1 << (8 * self.width(wide))
+    }
+}
+
 impl OpCode {
-    pub(crate) fn params(&self) -> u8 {
+    pub(crate) fn operands(&self) -> &'static [Operand] {
         match self {
-            Self::Constant | Self::GetLocal | Self::SetLocal => 1,
-            Self::Loop
+            Self::Jump
+            | Self::Loop
+            | Self::Constant
+            | Self::GetSuper
             | Self::DefGlobal
             | Self::GetGlobal
             | Self::SetGlobal
-            | Self::MakeClosure
-            | Self::GetCaptured => 2,
-            Self::Call => 6,
-            _ => 0,
+            | Self::JumpIfTrue
+            | Self::AddConstant
+            | Self::JumpIfFalse
+            | Self::MakeClosure => &[Operand::Short],
+            Self::Call
+            | Self::GetLocal
+            | Self::BuildMap
+            | Self::BuildList
+            | Self::SetLocal
+            | Self::GetCaptured
+            | Self::SetCaptured
+            | Self::SetLocalPop => &[Operand::Byte],
+            Self::Class | Self::GetProperty | Self::SetProperty | Self::InvokeSuper => {
+                &[Operand::Short, Operand::Byte]
+            }
+            Self::Invoke => &[Operand::Short, Operand::Byte, Operand::Byte],
+            _ => &[],
         }
     }
 }
@@ -60,25 +116,43 @@ impl From<u8> for OpCode {
             6 => Self::Jump,
             7 => Self::Less,
             8 => Self::Loop,
-            9 => Self::Equal,
-            10 => Self::Concat,
-            11 => Self::Divide,
-            12 => Self::Negate,
-            13 => Self::Return,
-            14 => Self::Greater,
-            15 => Self::GetLocal,
-            16 => Self::Constant,
-            17 => Self::Multiply,
-            18 => Self::NotEqual,
-            19 => Self::SetLocal,
-            20 => Self::DefGlobal,
-            21 => Self::GetGlobal,
-            22 => Self::LessEqual,
-            23 => Self::SetGlobal,
-            24 => Self::JumpIfFalse,
-            25 => Self::GetCaptured,
-            26 => Self::MakeClosure,
-            27 => Self::GreaterEqual,
+            9 => Self::Wide,
+            10 => Self::Class,
+            11 => Self::Equal,
+            12 => Self::Concat,
+            13 => Self::Divide,
+            14 => Self::Invoke,
+            15 => Self::Negate,
+            16 => Self::Return,
+            17 => Self::Greater,
+            18 => Self::BuildMap,
+            19 => Self::GetIndex,
+            20 => Self::GetSuper,
+            21 => Self::GetLocal,
+            22 => Self::Constant,
+            23 => Self::Multiply,
+            24 => Self::NotEqual,
+            25 => Self::SetIndex,
+            26 => Self::SetLocal,
+            27 => Self::Subtract,
+            28 => Self::BuildList,
+            29 => Self::DefGlobal,
+            30 => Self::GetGlobal,
+            31 => Self::LessEqual,
+            32 => Self::SetGlobal,
+            33 => Self::JumpIfTrue,
+            34 => Self::AddConstant,
+            35 => Self::FloorDivide,
+            36 => Self::JumpIfFalse,
+            37 => Self::GetCaptured,
+            38 => Self::GetProperty,
+            39 => Self::InvokeSuper,
+            40 => Self::MakeClosure,
+            41 => Self::SetProperty,
+            42 => Self::SetCaptured,
+            43 => Self::SetLocalPop,
+            44 => Self::GreaterEqual,
+            45 => Self::CloseCaptured,
             _ => Self::Invalid,
         }
     }
@@ -96,25 +170,43 @@ impl From<OpCode> for u8 {
             OpCode::Jump => 6,
             OpCode::Less => 7,
             OpCode::Loop => 8,
-            OpCode::Equal => 9,
-            OpCode::Concat => 10,
-            OpCode::Divide => 11,
-            OpCode::Negate => 12,
-            OpCode::Return => 13,
-            OpCode::Greater => 14,
-            OpCode::GetLocal => 15,
-            OpCode::Constant => 16,
-            OpCode::Multiply => 17,
-            OpCode::NotEqual => 18,
-            OpCode::SetLocal => 19,
-            OpCode::DefGlobal => 20,
-            OpCode::GetGlobal => 21,
-            OpCode::LessEqual => 22,
-            OpCode::SetGlobal => 23,
-            OpCode::JumpIfFalse => 24,
-            OpCode::GetCaptured => 25,
-            OpCode::MakeClosure => 26,
-            OpCode::GreaterEqual => 27,
+            OpCode::Wide => 9,
+            OpCode::Class => 10,
+            OpCode::Equal => 11,
+            OpCode::Concat => 12,
+            OpCode::Divide => 13,
+            OpCode::Invoke => 14,
+            OpCode::Negate => 15,
+            OpCode::Return => 16,
+            OpCode::Greater => 17,
+            OpCode::BuildMap => 18,
+            OpCode::GetIndex => 19,
+            OpCode::GetSuper => 20,
+            OpCode::GetLocal => 21,
+            OpCode::Constant => 22,
+            OpCode::Multiply => 23,
+            OpCode::NotEqual => 24,
+            OpCode::SetIndex => 25,
+            OpCode::SetLocal => 26,
+            OpCode::Subtract => 27,
+            OpCode::BuildList => 28,
+            OpCode::DefGlobal => 29,
+            OpCode::GetGlobal => 30,
+            OpCode::LessEqual => 31,
+            OpCode::SetGlobal => 32,
+            OpCode::JumpIfTrue => 33,
+            OpCode::AddConstant => 34,
+            OpCode::FloorDivide => 35,
+            OpCode::JumpIfFalse => 36,
+            OpCode::GetCaptured => 37,
+            OpCode::GetProperty => 38,
+            OpCode::InvokeSuper => 39,
+            OpCode::MakeClosure => 40,
+            OpCode::SetProperty => 41,
+            OpCode::SetCaptured => 42,
+            OpCode::SetLocalPop => 43,
+            OpCode::GreaterEqual => 44,
+            OpCode::CloseCaptured => 45,
             OpCode::Invalid => 255,
         }
     }
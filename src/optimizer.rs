@@ -0,0 +1,270 @@
+use crate::arithmetic;
+use crate::ast::{BinaryOp, Expr, ExprKind, LogicalOp, Stmt, UnaryOp};
+use crate::token::Span;
+use crate::value::Value;
+
+pub(crate) fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().map(optimize_statement).collect()
+}
+
+fn optimize_statement(statement: Stmt) -> Stmt {
+    match statement {
+        Stmt::Let {
+            name,
+            span,
+            initializer,
+        } => Stmt::Let {
+            name,
+            span,
+            initializer: initializer.map(fold),
+        },
+
+        Stmt::Fun(mut declaration) => {
+            declaration.body = optimize(declaration.body);
+            Stmt::Fun(declaration)
+        }
+
+        Stmt::Class(mut declaration) => {
+            declaration.fields.iter_mut().for_each(|field| {
+                field.initializer = field.initializer.take().map(fold);
+            });
+            declaration.methods.iter_mut().for_each(|method| {
+                method.function.body = optimize(std::mem::take(&mut method.function.body));
+            });
+            Stmt::Class(declaration)
+        }
+
+        Stmt::Return { span, value } => Stmt::Return {
+            span,
+            value: value.map(fold),
+        },
+
+        Stmt::Expression(expression) => Stmt::Expression(fold(expression)),
+
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = fold(condition);
+            let then_branch = optimize_statement(*then_branch);
+            let else_branch = else_branch.map(|branch| optimize_statement(*branch));
+
+            match literal(&condition).map(|value| is_truthy(&value)) {
+                Some(true) => then_branch,
+                Some(false) => else_branch.unwrap_or(Stmt::Block(vec![])),
+                None => Stmt::If {
+                    condition,
+                    then_branch: Box::new(then_branch),
+                    else_branch: else_branch.map(Box::new),
+                },
+            }
+        }
+
+        Stmt::While { condition, body } => {
+            let condition = fold(condition);
+            match literal(&condition).map(|value| is_truthy(&value)) {
+                Some(false) => Stmt::Block(vec![]),
+                _ => Stmt::While {
+                    condition,
+                    body: Box::new(optimize_statement(*body)),
+                },
+            }
+        }
+
+        Stmt::For {
+            span,
+            initializer,
+            condition,
+            increment,
+            body,
+        } => Stmt::For {
+            span,
+            initializer: initializer.map(|initializer| Box::new(optimize_statement(*initializer))),
+            condition: condition.map(fold),
+            increment: increment.map(fold),
+            body: Box::new(optimize_statement(*body)),
+        },
+
+        Stmt::Block(statements) => Stmt::Block(optimize(statements)),
+    }
+}
+
+fn fold(expression: Expr) -> Expr {
+    let span = expression.span;
+
+    let kind = match expression.kind {
+        ExprKind::Assign { name, value } => ExprKind::Assign {
+            name,
+            value: Box::new(fold(*value)),
+        },
+
+        ExprKind::Unary { operator, operand } => {
+            let operand = fold(*operand);
+            let folded = literal(&operand).and_then(|value| match (operator, value) {
+                (UnaryOp::Not, value) => Some(Value::Boolean(!is_truthy(&value))),
+                (UnaryOp::Negate, value) => arithmetic::negate(&value).ok(),
+            });
+
+            match folded {
+                Some(value) => return to_expression(value, span),
+                None => ExprKind::Unary {
+                    operator,
+                    operand: Box::new(operand),
+                },
+            }
+        }
+
+        ExprKind::Binary {
+            operator,
+            left,
+            right,
+        } => {
+            let left = fold(*left);
+            let right = fold(*right);
+            let folded = match (literal(&left), literal(&right)) {
+                (Some(left), Some(right)) => binary(operator, left, right),
+                _ => None,
+            };
+
+            match folded {
+                Some(value) => return to_expression(value, span),
+                None => ExprKind::Binary {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            }
+        }
+
+        ExprKind::Logical {
+            operator,
+            left,
+            right,
+        } => {
+            let left = fold(*left);
+            let right = fold(*right);
+
+            match (operator, literal(&left).map(|value| is_truthy(&value))) {
+                (LogicalOp::Or, Some(true)) | (LogicalOp::And, Some(false)) => return left,
+                (LogicalOp::Or, Some(false)) | (LogicalOp::And, Some(true)) => return right,
+                _ => ExprKind::Logical {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            }
+        }
+
+        ExprKind::Call { callee, args } => ExprKind::Call {
+            callee: Box::new(fold(*callee)),
+            args: args.into_iter().map(fold).collect(),
+        },
+
+        ExprKind::Get { object, name } => ExprKind::Get {
+            object: Box::new(fold(*object)),
+            name,
+        },
+
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => ExprKind::Set {
+            object: Box::new(fold(*object)),
+            name,
+            value: Box::new(fold(*value)),
+        },
+
+        ExprKind::List(elements) => ExprKind::List(elements.into_iter().map(fold).collect()),
+
+        ExprKind::Map(entries) => ExprKind::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (fold(key), fold(value)))
+                .collect(),
+        ),
+
+        ExprKind::Index { object, index } => ExprKind::Index {
+            object: Box::new(fold(*object)),
+            index: Box::new(fold(*index)),
+        },
+
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => ExprKind::SetIndex {
+            object: Box::new(fold(*object)),
+            index: Box::new(fold(*index)),
+            value: Box::new(fold(*value)),
+        },
+
+        kind => kind,
+    };
+
+    Expr::new(kind, span)
+}
+
+fn binary(operator: BinaryOp, left: Value, right: Value) -> Option<Value> {
+    let value = match operator {
+        BinaryOp::Equal => Value::Boolean(left == right),
+        BinaryOp::NotEqual => Value::Boolean(left != right),
+        BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEqual | BinaryOp::GreaterEqual
+            if !left.is_comparable(&right) =>
+        {
+            return None
+        }
+        BinaryOp::Less => Value::Boolean(left < right),
+        BinaryOp::Greater => Value::Boolean(left > right),
+        BinaryOp::LessEqual => Value::Boolean(left <= right),
+        BinaryOp::GreaterEqual => Value::Boolean(left >= right),
+        BinaryOp::Concat => Value::String(String::from(left) + &String::from(right)),
+
+        BinaryOp::Add => arithmetic::add(&left, &right).ok()?,
+        BinaryOp::Subtract => arithmetic::subtract(&left, &right).ok()?,
+        BinaryOp::Multiply => arithmetic::multiply(&left, &right).ok()?,
+        BinaryOp::Divide => arithmetic::divide(&left, &right).ok()?,
+        BinaryOp::FloorDivide => arithmetic::floor_divide(&left, &right).ok()?,
+        BinaryOp::Remainder => arithmetic::remainder(&left, &right).ok()?,
+    };
+
+    Some(value)
+}
+
+fn literal(expression: &Expr) -> Option<Value> {
+    match &expression.kind {
+        ExprKind::Nil => Some(Value::Nil),
+        ExprKind::Boolean(value) => Some(Value::Boolean(*value)),
+        ExprKind::Int(value) => Some(Value::Int(*value)),
+        ExprKind::BigInt(value) => Some(Value::from(value.clone())),
+        ExprKind::Number(value) => Some(Value::Number(*value)),
+        ExprKind::String(value) => Some(Value::String(value.clone())),
+        _ => None,
+    }
+}
+
+fn to_expression(value: Value, span: Span) -> Expr {
+    let kind = match value {
+        Value::Boolean(value) => ExprKind::Boolean(value),
+        Value::Int(value) => ExprKind::Int(value),
+        Value::BigInt(value) => ExprKind::BigInt(value.as_ref().clone()),
+        Value::Number(value) => ExprKind::Number(value),
+        Value::String(value) => ExprKind::String(value),
+        _ => ExprKind::Nil,
+    };
+
+    Expr::new(kind, span)
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Nil => false,
+        Value::Boolean(value) => *value,
+        Value::Int(value) => *value != 0,
+        Value::BigInt(_) => true,
+        Value::Number(value) => *value != 0.0,
+        Value::String(value) => !value.is_empty(),
+        _ => true,
+    }
+}
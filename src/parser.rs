@@ -0,0 +1,792 @@
+use crate::ast::{
+    BinaryOp, ClassDecl, Expr, ExprKind, Field, FunDecl, LogicalOp, Method, Stmt, UnaryOp,
+};
+use crate::error::{ErrorContext, LoxError};
+use crate::scanner::Tokens;
+use crate::token::{Kind, Span, Token};
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn of(kind: &Kind) -> Precedence {
+        match kind {
+            Kind::Or => Self::Or,
+            Kind::And => Self::And,
+            Kind::EqualEqual | Kind::BangEqual => Self::Equality,
+            Kind::Less | Kind::Greater | Kind::LessEqual | Kind::GreaterEqual => Self::Comparison,
+            Kind::Plus | Kind::Minus | Kind::Concat => Self::Term,
+            Kind::Star | Kind::Slash | Kind::SlashSlash | Kind::Percent => Self::Factor,
+            Kind::LeftParen | Kind::Dot | Kind::LeftBracket => Self::Call,
+            _ => Self::None,
+        }
+    }
+
+    fn next(&self) -> Precedence {
+        match self {
+            Self::None => Self::Assignment,
+            Self::Assignment => Self::Or,
+            Self::Or => Self::And,
+            Self::And => Self::Equality,
+            Self::Equality => Self::Comparison,
+            Self::Comparison => Self::Term,
+            Self::Term => Self::Factor,
+            Self::Factor => Self::Unary,
+            Self::Unary => Self::Call,
+            Self::Call | Self::Primary => Self::Primary,
+        }
+    }
+}
+
+pub(crate) struct Parser<'a> {
+    panic_mode: bool,
+    errors: Vec<LoxError>,
+    scanner: Tokens<'a>,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(source: &'a str) -> Parser<'a> {
+        Parser {
+            errors: vec![],
+            panic_mode: false,
+            scanner: Tokens::new(source),
+        }
+    }
+
+    pub(crate) fn parse(mut self) -> Result<Vec<Stmt>, Vec<LoxError>> {
+        let mut statements = vec![];
+        while !self.check(Kind::Eof) && self.scanner.peek().is_some() {
+            statements.push(self.declaration());
+        }
+
+        match self.errors.is_empty() {
+            true => Ok(statements),
+            false => Err(self.errors),
+        }
+    }
+
+    fn declaration(&mut self) -> Stmt {
+        let statement = match self.scanner.peek().map(|token| token.kind()) {
+            Some(Kind::Let) => {
+                self.scanner.next();
+                self.let_declaration()
+            }
+
+            Some(Kind::Fun) => {
+                self.scanner.next();
+                match self.scanner.next() {
+                    Some(token) if token.kind() == Kind::Identifier => {
+                        let name = token.value().unwrap().into();
+                        Stmt::Fun(self.function(name, token.span()))
+                    }
+
+                    token => {
+                        self.unexpected(token);
+                        Stmt::Block(vec![])
+                    }
+                }
+            }
+
+            Some(Kind::Class) => {
+                self.scanner.next();
+                self.class_declaration()
+            }
+
+            _ => self.statement(),
+        };
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+        statement
+    }
+
+    fn let_declaration(&mut self) -> Stmt {
+        match self.scanner.next() {
+            Some(token) if token.kind() == Kind::Identifier => {
+                let initializer = match self.matches(Kind::Equal) {
+                    true => Some(self.expression()),
+                    false => None,
+                };
+                self.expect(Kind::Semicolon);
+
+                Stmt::Let {
+                    initializer,
+                    span: token.span(),
+                    name: token.value().unwrap().into(),
+                }
+            }
+
+            token => {
+                self.unexpected(token);
+                Stmt::Block(vec![])
+            }
+        }
+    }
+
+    fn function(&mut self, name: String, span: Span) -> FunDecl {
+        self.expect(Kind::LeftParen);
+        let params = self.parameters();
+        let body = match self.statement() {
+            Stmt::Block(statements) => statements,
+            statement => vec![statement],
+        };
+
+        FunDecl {
+            name,
+            span,
+            params,
+            body,
+        }
+    }
+
+    fn class_declaration(&mut self) -> Stmt {
+        let Some((name, span)) = self.expect_identifier() else {
+            return Stmt::Block(vec![]);
+        };
+
+        let superclass = match self.matches(Kind::Expands) {
+            true => {
+                let Some(superclass) = self.expect_identifier() else {
+                    return Stmt::Block(vec![]);
+                };
+                Some(superclass)
+            }
+            false => None,
+        };
+
+        self.expect(Kind::LeftBrace);
+        let mut fields = vec![];
+        let mut methods = vec![];
+        let mut methods_started = false;
+
+        loop {
+            if self.panic_mode {
+                self.synchronize_member();
+            }
+            let is_pub = self.matches(Kind::Pub);
+
+            match self.scanner.peek().map(|token| token.kind()) {
+                Some(Kind::RightBrace) | Some(Kind::Eof) | None if !is_pub => break,
+                Some(Kind::Let | Kind::Class | Kind::If | Kind::While | Kind::Return)
+                    if !is_pub =>
+                {
+                    break
+                }
+
+                Some(Kind::RightBrace) | Some(Kind::Eof) | None => {
+                    self.error(LoxError::new(
+                        "Expected a field or method after pub",
+                        ErrorContext::Compile,
+                        Some(self.scanner.previous()),
+                    ));
+                    break;
+                }
+
+                Some(Kind::Fun) => {
+                    self.scanner.next();
+                    methods_started = true;
+                    let Some((name, span)) = self.expect_identifier() else {
+                        continue;
+                    };
+                    let function = self.function(name, span);
+                    methods.push(Method { is_pub, function });
+                }
+
+                Some(Kind::Identifier) if !methods_started => {
+                    let (name, span) = self.expect_identifier().unwrap();
+                    let initializer = match self.matches(Kind::Equal) {
+                        true => Some(self.expression()),
+                        false => None,
+                    };
+                    self.expect(Kind::Semicolon);
+
+                    fields.push(Field {
+                        name,
+                        span,
+                        is_pub,
+                        initializer,
+                    });
+                }
+
+                Some(Kind::Identifier) => {
+                    self.scanner.next();
+                    self.error(LoxError::new(
+                        "Fields must be declared before methods",
+                        ErrorContext::Compile,
+                        Some(self.scanner.previous()),
+                    ));
+                }
+
+                _ => {
+                    let token = self.scanner.next();
+                    self.unexpected(token);
+                }
+            }
+        }
+        self.expect(Kind::RightBrace);
+
+        Stmt::Class(ClassDecl {
+            name,
+            span,
+            superclass,
+            fields,
+            methods,
+        })
+    }
+
+    fn parameters(&mut self) -> Vec<(String, Span)> {
+        let mut params = vec![];
+
+        loop {
+            match self.scanner.next() {
+                Some(token) if token.kind() == Kind::Identifier => {
+                    params.push((token.value().unwrap().into(), token.span()));
+
+                    match self.scanner.peek() {
+                        Some(token) if token.kind() == Kind::Comma => {
+                            self.scanner.next();
+                            continue;
+                        }
+
+                        Some(token) if token.kind() == Kind::RightParen => {
+                            self.scanner.next();
+                            break;
+                        }
+
+                        token => {
+                            let token = token.cloned();
+                            self.unexpected(token);
+                            break;
+                        }
+                    }
+                }
+
+                Some(token) if token.kind() == Kind::RightParen => {
+                    break;
+                }
+
+                token => {
+                    self.unexpected(token);
+                    break;
+                }
+            }
+        }
+
+        params
+    }
+
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut statements = vec![];
+        loop {
+            match self.scanner.peek().map(|token| token.kind()) {
+                Some(Kind::RightBrace) | Some(Kind::Eof) | None => break,
+                _ => statements.push(self.declaration()),
+            }
+        }
+        self.expect(Kind::RightBrace);
+        statements
+    }
+
+    fn statement(&mut self) -> Stmt {
+        match self.scanner.peek().map(|token| token.kind()) {
+            Some(Kind::If) => {
+                self.scanner.next();
+                let condition = self.expression();
+                let then_branch = Box::new(self.statement());
+                let else_branch = match self.matches(Kind::Else) {
+                    true => Some(Box::new(self.statement())),
+                    false => None,
+                };
+
+                Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                }
+            }
+
+            Some(Kind::While) => {
+                self.scanner.next();
+                let condition = self.expression();
+                let body = Box::new(self.statement());
+                Stmt::While { condition, body }
+            }
+
+            Some(Kind::For) => {
+                self.scanner.next();
+                self.for_statement()
+            }
+
+            Some(Kind::Return) => {
+                self.scanner.next();
+                let span = self.scanner.previous();
+                let value = match self.check(Kind::Semicolon) {
+                    true => None,
+                    false => Some(self.expression()),
+                };
+                self.expect(Kind::Semicolon);
+                Stmt::Return { span, value }
+            }
+
+            Some(Kind::LeftBrace) => {
+                self.scanner.next();
+                Stmt::Block(self.block())
+            }
+
+            _ => {
+                let expression = self.expression();
+                self.expect(Kind::Semicolon);
+                Stmt::Expression(expression)
+            }
+        }
+    }
+
+    fn for_statement(&mut self) -> Stmt {
+        let span = self.scanner.previous();
+
+        let initializer = match self.scanner.peek().map(|token| token.kind()) {
+            Some(Kind::Let) => {
+                self.scanner.next();
+                Some(Box::new(self.let_declaration()))
+            }
+
+            Some(Kind::Semicolon) => {
+                self.scanner.next();
+                None
+            }
+
+            _ => {
+                let expression = self.expression();
+                self.expect(Kind::Semicolon);
+                Some(Box::new(Stmt::Expression(expression)))
+            }
+        };
+
+        let condition = match self.check(Kind::Semicolon) {
+            true => None,
+            false => Some(self.expression()),
+        };
+        self.expect(Kind::Semicolon);
+
+        let increment = match self.check(Kind::LeftBrace) {
+            true => None,
+            false => Some(self.expression()),
+        };
+        let body = Box::new(self.statement());
+
+        Stmt::For {
+            span,
+            initializer,
+            condition,
+            increment,
+            body,
+        }
+    }
+
+    fn expression(&mut self) -> Expr {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
+        let can_assign = precedence <= Precedence::Assignment;
+        let token = self.scanner.next();
+        let mut expression = self.prefix(token, can_assign);
+
+        while let Some(token) = self.scanner.peek() {
+            if precedence > Precedence::of(&token.kind()) {
+                break;
+            }
+            let token = self.scanner.next().unwrap();
+            expression = self.infix(token, expression, can_assign);
+        }
+
+        if can_assign && self.matches(Kind::Equal) {
+            self.error(LoxError::new(
+                "Invalid assignment target",
+                ErrorContext::Compile,
+                Some(self.scanner.previous()),
+            ));
+        }
+
+        expression
+    }
+
+    fn prefix(&mut self, token: Option<Token>, can_assign: bool) -> Expr {
+        let Some(token) = token else {
+            self.unexpected(None);
+            return Expr::new(ExprKind::Nil, self.scanner.previous());
+        };
+        let span = token.span();
+
+        let kind = match token.kind() {
+            Kind::Nil => ExprKind::Nil,
+            Kind::True => ExprKind::Boolean(true),
+            Kind::False => ExprKind::Boolean(false),
+            Kind::Number => match token.value() {
+                Some(Value::Int(value)) => ExprKind::Int(value),
+                Some(Value::BigInt(value)) => ExprKind::BigInt(value.as_ref().clone()),
+                value => ExprKind::Number(value.unwrap().into()),
+            },
+            Kind::String => ExprKind::String(token.value().unwrap().into()),
+            Kind::This => ExprKind::This,
+
+            Kind::Not => ExprKind::Unary {
+                operator: UnaryOp::Not,
+                operand: Box::new(self.parse_precedence(Precedence::Unary)),
+            },
+
+            Kind::Minus => ExprKind::Unary {
+                operator: UnaryOp::Negate,
+                operand: Box::new(self.parse_precedence(Precedence::Unary)),
+            },
+
+            Kind::LeftParen => {
+                let expression = self.expression();
+                self.expect(Kind::RightParen);
+                return expression;
+            }
+
+            Kind::LeftBracket => ExprKind::List(self.arguments(Kind::RightBracket)),
+
+            Kind::LeftBrace => ExprKind::Map(self.entries()),
+
+            Kind::Super => {
+                self.expect(Kind::Dot);
+                match self.expect_identifier() {
+                    Some((name, _)) => ExprKind::Super(name),
+                    None => ExprKind::Nil,
+                }
+            }
+
+            Kind::Identifier => {
+                let name: String = token.value().unwrap().into();
+                match can_assign && self.matches(Kind::Equal) {
+                    true => ExprKind::Assign {
+                        name,
+                        value: Box::new(self.expression()),
+                    },
+                    false => ExprKind::Variable(name),
+                }
+            }
+
+            _ => {
+                self.unexpected(Some(token));
+                ExprKind::Nil
+            }
+        };
+
+        Expr::new(kind, span)
+    }
+
+    fn infix(&mut self, token: Token, left: Expr, can_assign: bool) -> Expr {
+        let span = token.span();
+        let left = Box::new(left);
+
+        let operator = match token.kind() {
+            Kind::Or | Kind::And => {
+                let right = Box::new(self.parse_precedence(Precedence::of(&token.kind()).next()));
+                let operator = match token.kind() {
+                    Kind::Or => LogicalOp::Or,
+                    _ => LogicalOp::And,
+                };
+
+                return Expr::new(
+                    ExprKind::Logical {
+                        operator,
+                        left,
+                        right,
+                    },
+                    span,
+                );
+            }
+
+            Kind::LeftParen => {
+                let args = self.arguments(Kind::RightParen);
+                return Expr::new(ExprKind::Call { callee: left, args }, span);
+            }
+
+            Kind::LeftBracket => {
+                let index = Box::new(self.expression());
+                self.expect(Kind::RightBracket);
+
+                let kind = match can_assign && self.matches(Kind::Equal) {
+                    true => ExprKind::SetIndex {
+                        index,
+                        object: left,
+                        value: Box::new(self.expression()),
+                    },
+                    false => ExprKind::Index {
+                        index,
+                        object: left,
+                    },
+                };
+                return Expr::new(kind, span);
+            }
+
+            Kind::Dot => {
+                let Some((name, span)) = self.expect_identifier() else {
+                    return *left;
+                };
+
+                let kind = match can_assign && self.matches(Kind::Equal) {
+                    true => ExprKind::Set {
+                        name,
+                        object: left,
+                        value: Box::new(self.expression()),
+                    },
+                    false => ExprKind::Get { name, object: left },
+                };
+                return Expr::new(kind, span);
+            }
+
+            Kind::Plus => BinaryOp::Add,
+            Kind::Less => BinaryOp::Less,
+            Kind::Star => BinaryOp::Multiply,
+            Kind::Minus => BinaryOp::Subtract,
+            Kind::Slash => BinaryOp::Divide,
+            Kind::SlashSlash => BinaryOp::FloorDivide,
+            Kind::Concat => BinaryOp::Concat,
+            Kind::Greater => BinaryOp::Greater,
+            Kind::Percent => BinaryOp::Remainder,
+            Kind::BangEqual => BinaryOp::NotEqual,
+            Kind::LessEqual => BinaryOp::LessEqual,
+            Kind::EqualEqual => BinaryOp::Equal,
+            Kind::GreaterEqual => BinaryOp::GreaterEqual,
+
+            _ => {
+                self.unexpected(Some(token));
+                return *left;
+            }
+        };
+
+        let right = Box::new(self.parse_precedence(Precedence::of(&token.kind()).next()));
+        Expr::new(
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            },
+            span,
+        )
+    }
+
+    fn arguments(&mut self, closing: Kind) -> Vec<Expr> {
+        let mut args = vec![];
+        loop {
+            match self.scanner.peek() {
+                Some(token) if token.kind() == closing => {
+                    self.scanner.next();
+                    break;
+                }
+
+                Some(_) => {
+                    args.push(self.expression());
+                    match self.scanner.peek() {
+                        Some(token) if token.kind() == Kind::Comma => {
+                            self.scanner.next();
+                            continue;
+                        }
+
+                        Some(token) if token.kind() == closing => {
+                            self.scanner.next();
+                            break;
+                        }
+
+                        token => {
+                            let token = token.cloned();
+                            self.unexpected(token);
+                            break;
+                        }
+                    }
+                }
+
+                None => {
+                    self.unexpected(None);
+                    break;
+                }
+            }
+        }
+        args
+    }
+
+    fn entries(&mut self) -> Vec<(Expr, Expr)> {
+        let mut entries = vec![];
+        while !self.matches(Kind::RightBrace) {
+            if self.scanner.peek().is_none() {
+                self.unexpected(None);
+                break;
+            }
+
+            let key = self.expression();
+            self.expect(Kind::Colon);
+            entries.push((key, self.expression()));
+
+            if !self.matches(Kind::Comma) {
+                self.expect(Kind::RightBrace);
+                break;
+            }
+        }
+        entries
+    }
+
+    fn check(&mut self, kind: Kind) -> bool {
+        matches!(self.scanner.peek(), Some(token) if token.kind() == kind)
+    }
+
+    fn matches(&mut self, kind: Kind) -> bool {
+        let found = self.check(kind);
+        if found {
+            self.scanner.next();
+        }
+        found
+    }
+
+    fn error(&mut self, error: LoxError) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        self.errors.push(error);
+    }
+
+    fn unexpected(&mut self, token: Option<Token>) {
+        let error = match token {
+            Some(token) if token.kind() == Kind::Error => {
+                let message: String = token.value().unwrap().into();
+                LoxError::new(&message, ErrorContext::Compile, Some(token.span()))
+            }
+            Some(token) if token.kind() == Kind::SlashSlash => LoxError::new(
+                format!("Unexpected {}", token).as_str(),
+                ErrorContext::Compile,
+                Some(token.span()),
+            )
+            .with_help("// is floor division, line comments start with #"),
+            Some(token) => LoxError::new(
+                format!("Unexpected {}", token).as_str(),
+                ErrorContext::Compile,
+                Some(token.span()),
+            ),
+            None => LoxError::new(
+                "Unexpected end of script",
+                ErrorContext::Compile,
+                Some(self.scanner.previous()),
+            ),
+        };
+        self.error(error);
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        if self.scanner.previous_kind() == Some(Kind::Semicolon) {
+            return;
+        }
+
+        let mut depth = 0;
+        while let Some(token) = self.scanner.peek() {
+            match token.kind() {
+                Kind::Eof => return,
+                Kind::Fun | Kind::Let | Kind::Class | Kind::If | Kind::While | Kind::Return
+                    if depth == 0 =>
+                {
+                    return
+                }
+                Kind::RightBrace if depth == 0 => return,
+                Kind::RightBrace => depth -= 1,
+                Kind::LeftBrace => depth += 1,
+                Kind::Semicolon if depth == 0 => {
+                    self.scanner.next();
+                    return;
+                }
+                _ => (),
+            }
+            self.scanner.next();
+        }
+    }
+
+    fn synchronize_member(&mut self) {
+        self.panic_mode = false;
+        if self.scanner.previous_kind() == Some(Kind::Semicolon) {
+            return;
+        }
+
+        let mut depth = 0;
+        while let Some(token) = self.scanner.peek() {
+            match token.kind() {
+                Kind::Eof => return,
+                Kind::Pub
+                | Kind::Fun
+                | Kind::RightBrace
+                | Kind::Let
+                | Kind::Class
+                | Kind::If
+                | Kind::While
+                | Kind::Return
+                    if depth == 0 =>
+                {
+                    return
+                }
+                Kind::RightBrace => depth -= 1,
+                Kind::LeftBrace => depth += 1,
+                Kind::Semicolon if depth == 0 => {
+                    self.scanner.next();
+                    return;
+                }
+                _ => (),
+            }
+            self.scanner.next();
+        }
+    }
+
+    fn expect(&mut self, kind: Kind) {
+        match self.scanner.peek() {
+            Some(token) if token.kind() == kind => {
+                self.scanner.next();
+            }
+
+            Some(token) => {
+                let message = format!("Expected {}, got {}", kind, token);
+                let span = token.span();
+                self.error(LoxError::new(&message, ErrorContext::Compile, Some(span)));
+            }
+
+            None => self.unexpected(None),
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Option<(String, Span)> {
+        match self.scanner.peek().cloned() {
+            Some(token) if token.kind() == Kind::Identifier => {
+                self.scanner.next();
+                Some((token.value().unwrap().into(), token.span()))
+            }
+
+            Some(token) => {
+                self.error(LoxError::new(
+                    format!("Expected {}, got {}", Kind::Identifier, token).as_str(),
+                    ErrorContext::Compile,
+                    Some(token.span()),
+                ));
+                None
+            }
+
+            None => {
+                self.unexpected(None);
+                None
+            }
+        }
+    }
+}
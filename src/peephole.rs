@@ -0,0 +1,322 @@
+use crate::function::Function;
+use crate::op::OpCode;
+use crate::token::Span;
+
+struct Instruction {
+    op: OpCode,
+    span: Span,
+    operands: Vec<usize>,
+    target: Option<usize>,
+}
+
+impl Instruction {
+    fn size(&self, operands: &[usize]) -> usize {
+        let layout = self.op.operands();
+        let wide = !layout
+            .iter()
+            .zip(operands)
+            .all(|(operand, value)| operand.fits(*value, false));
+        let width: usize = layout.iter().map(|operand| operand.width(wide)).sum();
+        1 + wide as usize + width
+    }
+
+    fn is_jump(&self) -> bool {
+        matches!(
+            self.op,
+            OpCode::Jump | OpCode::Loop | OpCode::JumpIfTrue | OpCode::JumpIfFalse
+        )
+    }
+
+    fn is_unconditional(&self) -> bool {
+        matches!(self.op, OpCode::Jump | OpCode::Loop)
+    }
+}
+
+pub(crate) fn peephole(function: &mut Function) {
+    let mut instructions = decode(function);
+
+    while rewrite(&mut instructions) {}
+
+    encode(function, &instructions);
+}
+
+pub(crate) fn narrow_jumps(function: &mut Function) {
+    let instructions = decode(function);
+    encode(function, &instructions);
+}
+
+fn decode(function: &Function) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offsets = vec![];
+    let mut offset = 0;
+
+    while let Some((op, operands, next)) = function.instruction(offset) {
+        offsets.push(offset);
+        instructions.push(Instruction {
+            op,
+            operands,
+            target: None,
+            span: function.span(offset).unwrap_or_default(),
+        });
+        offset = next;
+    }
+    offsets.push(offset);
+
+    for (index, instruction) in instructions.iter_mut().enumerate() {
+        let target = match instruction.op {
+            OpCode::Loop => offsets[index + 1] - instruction.operands[0],
+            _ if instruction.is_jump() => offsets[index + 1] + instruction.operands[0],
+            _ => continue,
+        };
+        instruction.target = offsets.binary_search(&target).ok();
+    }
+
+    instructions
+}
+
+fn encode(function: &mut Function, instructions: &[Instruction]) {
+    let mut operands: Vec<Vec<usize>> = instructions
+        .iter()
+        .map(|instruction| match instruction.target {
+            Some(_) => vec![0],
+            None => instruction.operands.clone(),
+        })
+        .collect();
+
+    loop {
+        let mut offsets = vec![0];
+        for (instruction, operands) in instructions.iter().zip(&operands) {
+            offsets.push(offsets.last().unwrap() + instruction.size(operands));
+        }
+
+        let mut changed = false;
+        for (index, instruction) in instructions.iter().enumerate() {
+            let Some(target) = instruction.target.map(|target| offsets[target]) else {
+                continue;
+            };
+            let end = offsets[index + 1];
+            let distance = match target > end {
+                true => target - end,
+                false => end - target,
+            };
+            if operands[index] != [distance] {
+                operands[index] = vec![distance];
+                changed = true;
+            }
+        }
+
+        if !changed {
+            function.clear_code();
+            for (index, instruction) in instructions.iter().enumerate() {
+                let op = match instruction.target.map(|target| offsets[target]) {
+                    Some(target) if instruction.is_unconditional() && target <= offsets[index] => {
+                        OpCode::Loop
+                    }
+                    Some(_) if instruction.is_unconditional() => OpCode::Jump,
+                    _ => instruction.op,
+                };
+
+                function.set_span(instruction.span);
+                function.add_instruction(op, &operands[index]);
+            }
+            return;
+        }
+    }
+}
+
+fn rewrite(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+
+    for index in 0..instructions.len() {
+        if let Some(target) = thread(instructions, index) {
+            instructions[index].target = Some(target);
+            changed = true;
+        }
+    }
+
+    let mut live = Liveness::new(instructions);
+    let mut index = 0;
+    while index < instructions.len() {
+        if live.is_removed(index) {
+            index += 1;
+            continue;
+        }
+
+        let next = live.find(index + 1);
+        let after = live.find(next + 1);
+        let op_at = |at: usize| instructions.get(at).map(|instruction| instruction.op);
+        let current = &instructions[index];
+        let target = current.target.map(|target| live.find(target));
+
+        match (current.op, op_at(next)) {
+            (OpCode::Jump, _) if target == Some(next) => {
+                live.remove(index);
+                changed = true;
+                index = next;
+                continue;
+            }
+
+            (OpCode::SetLocal, Some(OpCode::Pop)) if !live.targeted(next) => {
+                instructions[index].op = OpCode::SetLocalPop;
+                live.remove(next);
+                changed = true;
+            }
+
+            (OpCode::Constant, Some(OpCode::Add)) if !live.targeted(next) => {
+                let span = instructions[next].span;
+                fuse_add(&mut instructions[index], span);
+                live.remove(next);
+                changed = true;
+            }
+
+            (OpCode::Not, Some(OpCode::JumpIfFalse | OpCode::JumpIfTrue))
+                if !live.targeted(index)
+                    && !live.targeted(next)
+                    && pops_on_both_paths(instructions, &mut live, next) =>
+            {
+                let span = instructions[index].span;
+                live.remove(index);
+                let jump = &mut instructions[next];
+                jump.op = match jump.op {
+                    OpCode::JumpIfFalse => OpCode::JumpIfTrue,
+                    _ => OpCode::JumpIfFalse,
+                };
+                jump.span = span;
+                changed = true;
+                index = next;
+                continue;
+            }
+
+            (OpCode::JumpIfFalse | OpCode::JumpIfTrue, Some(OpCode::Jump))
+                if target == Some(after)
+                    && !live.targeted(next)
+                    && instructions[next].target > Some(next) =>
+            {
+                let target = instructions[next].target;
+                let jump = &mut instructions[index];
+                jump.op = match jump.op {
+                    OpCode::JumpIfFalse => OpCode::JumpIfTrue,
+                    _ => OpCode::JumpIfFalse,
+                };
+                jump.target = target;
+                live.remove(next);
+                changed = true;
+            }
+
+            _ => {}
+        }
+
+        index += 1;
+    }
+
+    live.compact(instructions);
+    changed
+}
+
+fn thread(instructions: &[Instruction], index: usize) -> Option<usize> {
+    let instruction = &instructions[index];
+    let mut target = instruction.target?;
+    let mut visited = vec![index];
+
+    while let Some(next) = instructions.get(target) {
+        let follows = next.is_unconditional()
+            || (next.op == instruction.op && !instruction.is_unconditional());
+        match next.target {
+            Some(next_target) if follows && !visited.contains(&next_target) => {
+                visited.push(target);
+                target = next_target;
+            }
+            _ => break,
+        }
+    }
+
+    let forward = instruction.is_unconditional() || target > index;
+    (target != instruction.target? && forward).then_some(target)
+}
+
+fn pops_on_both_paths(instructions: &[Instruction], live: &mut Liveness, jump: usize) -> bool {
+    let mut pops = |at: Option<usize>| {
+        at.map(|at| live.find(at))
+            .and_then(|at| instructions.get(at))
+            .is_some_and(|instruction| instruction.op == OpCode::Pop)
+    };
+    pops(Some(jump + 1)) && pops(instructions[jump].target)
+}
+
+fn fuse_add(instruction: &mut Instruction, span: Span) {
+    instruction.op = OpCode::AddConstant;
+    instruction.span = span;
+}
+
+// removed instructions forward to the next live one, along with their inbound jumps
+struct Liveness {
+    next: Vec<usize>,
+    inbound: Vec<usize>,
+}
+
+impl Liveness {
+    fn new(instructions: &[Instruction]) -> Liveness {
+        let mut inbound = vec![0; instructions.len() + 1];
+        for target in instructions
+            .iter()
+            .filter_map(|instruction| instruction.target)
+        {
+            inbound[target] += 1;
+        }
+
+        Liveness {
+            next: (0..=instructions.len()).collect(),
+            inbound,
+        }
+    }
+
+    fn is_removed(&self, index: usize) -> bool {
+        self.next[index] != index
+    }
+
+    fn targeted(&self, index: usize) -> bool {
+        self.inbound[index] > 0
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let index = index.min(self.next.len() - 1);
+        let mut live = index;
+        while self.next[live] != live {
+            live = self.next[live];
+        }
+
+        let mut at = index;
+        while self.next[at] != live && at != live {
+            let next = self.next[at];
+            self.next[at] = live;
+            at = next;
+        }
+        live
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.next[index] = index + 1;
+        let next = self.find(index + 1);
+        self.inbound[next] += self.inbound[index];
+    }
+
+    fn compact(&mut self, instructions: &mut Vec<Instruction>) {
+        let mut positions = Vec::with_capacity(self.next.len());
+        let mut count = 0;
+        for index in 0..self.next.len() {
+            positions.push(count);
+            if !self.is_removed(index) {
+                count += 1;
+            }
+        }
+
+        let mut index = 0;
+        instructions.retain(|_| {
+            index += 1;
+            self.next[index - 1] == index - 1
+        });
+        for instruction in instructions.iter_mut() {
+            instruction.target = instruction.target.map(|target| positions[target]);
+        }
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{ClassDecl, Expr, ExprKind, FunDecl, Stmt};
+use crate::error::{ErrorContext, LoxError};
+use crate::nif::resolve_nif;
+use crate::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Binding {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Resolution {
+    captured: HashSet<(Span, String)>,
+    bindings: HashMap<(Span, String), Binding>,
+    upvalues: HashMap<(Span, String), Vec<(bool, usize)>>,
+}
+
+impl Resolution {
+    pub(crate) fn binding(&self, span: Span, name: &str) -> Binding {
+        self.bindings
+            .get(&(span, name.to_string()))
+            .copied()
+            .unwrap_or(Binding::Global)
+    }
+
+    pub(crate) fn is_captured(&self, span: Span, name: &str) -> bool {
+        self.captured.contains(&(span, name.to_string()))
+    }
+
+    pub(crate) fn upvalues(&self, span: Span, name: &str) -> Vec<(bool, usize)> {
+        self.upvalues
+            .get(&(span, name.to_string()))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+}
+
+struct Local {
+    name: String,
+    span: Span,
+    depth: usize,
+    used: bool,
+    defined: bool,
+    kind: Option<&'static str>,
+}
+
+struct FunctionScope {
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<(bool, usize)>,
+}
+
+pub(crate) struct Resolver {
+    scope_depth: usize,
+    globals: Vec<String>,
+    errors: Vec<LoxError>,
+    warnings: Vec<LoxError>,
+    classes: Vec<Option<String>>,
+    functions: Vec<FunctionScope>,
+    resolution: Resolution,
+}
+
+impl Resolver {
+    pub(crate) fn new() -> Resolver {
+        Resolver {
+            scope_depth: 0,
+            globals: vec![],
+            errors: vec![],
+            warnings: vec![],
+            classes: vec![],
+            functions: vec![FunctionScope {
+                kind: FunctionKind::Script,
+                locals: vec![],
+                upvalues: vec![],
+            }],
+            resolution: Resolution::default(),
+        }
+    }
+
+    pub(crate) fn resolve(
+        mut self,
+        statements: &[Stmt],
+    ) -> (Result<Resolution, Vec<LoxError>>, Vec<LoxError>) {
+        statements
+            .iter()
+            .for_each(|statement| self.resolve_statement(statement));
+
+        match self.errors.is_empty() {
+            true => (Ok(self.resolution), self.warnings),
+            false => (Err(self.errors), self.warnings),
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Let {
+                name,
+                span,
+                initializer,
+            } => {
+                if self.scope_depth > 0 {
+                    self.declare(name, *span, Some("variable"), false);
+                }
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+
+            Stmt::Fun(declaration) => {
+                let name = &declaration.name;
+                if (self.scope_depth == 0 && self.globals.contains(name))
+                    || resolve_nif(name).is_some()
+                {
+                    self.error(
+                        format!("Function {} already exists", name).as_str(),
+                        declaration.span,
+                    );
+                    return;
+                }
+
+                if self.scope_depth > 0 {
+                    self.declare(name, declaration.span, Some("function"), true);
+                }
+                self.resolve_function(declaration, FunctionKind::Function);
+                self.define(name);
+            }
+
+            Stmt::Class(declaration) => self.resolve_class(declaration),
+
+            Stmt::Return { span, value } => {
+                if self.function().kind == FunctionKind::Script {
+                    self.error("Cannot return from top-level code", *span);
+                }
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+            }
+
+            Stmt::Expression(expression) => self.resolve_expression(expression),
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+
+            Stmt::While { condition, body } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+
+            Stmt::For {
+                span,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.scope_depth += 1;
+                let variable = match initializer {
+                    Some(initializer) => {
+                        let size = self.function().locals.len();
+                        self.resolve_statement(initializer);
+                        match self.function().locals.len() > size {
+                            true => self
+                                .function()
+                                .locals
+                                .last()
+                                .map(|local| local.name.clone()),
+                            false => None,
+                        }
+                    }
+                    None => None,
+                };
+
+                if let Some(condition) = condition {
+                    self.resolve_expression(condition);
+                }
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+
+                match variable {
+                    Some(variable) => {
+                        self.scope_depth += 1;
+                        self.declare(&variable, *span, None, true);
+                        self.resolve_statement(body);
+                        self.end_scope();
+                        self.scope_depth -= 1;
+                    }
+                    None => self.resolve_statement(body),
+                }
+                self.end_scope();
+                self.scope_depth -= 1;
+            }
+
+            Stmt::Block(statements) => {
+                self.scope_depth += 1;
+                statements
+                    .iter()
+                    .for_each(|statement| self.resolve_statement(statement));
+                self.end_scope();
+                self.scope_depth -= 1;
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, declaration: &FunDecl, kind: FunctionKind) {
+        let this = match kind {
+            FunctionKind::Method => Some(("this".to_string(), declaration.span)),
+            _ => None,
+        };
+
+        self.functions.push(FunctionScope {
+            kind,
+            locals: vec![],
+            upvalues: vec![],
+        });
+        self.scope_depth += 1;
+
+        if let Some((name, span)) = this {
+            self.declare(&name, span, None, true);
+        }
+        declaration
+            .params
+            .iter()
+            .for_each(|(name, span)| self.declare(name, *span, Some("parameter"), true));
+        declaration
+            .body
+            .iter()
+            .for_each(|statement| self.resolve_statement(statement));
+
+        self.end_scope();
+        self.scope_depth -= 1;
+        let function = self.functions.pop().unwrap();
+        self.resolution.upvalues.insert(
+            (declaration.span, declaration.name.clone()),
+            function.upvalues,
+        );
+    }
+
+    fn resolve_class(&mut self, declaration: &ClassDecl) {
+        let class_name = &declaration.name;
+
+        if let Some((superclass, span)) = &declaration.superclass {
+            if superclass == class_name {
+                self.error(
+                    format!("Class {} cannot expand itself", class_name).as_str(),
+                    *span,
+                );
+            }
+            self.resolve_variable(superclass, *span);
+        }
+        if self.scope_depth > 0 {
+            self.declare(class_name, declaration.span, Some("class"), true);
+        }
+        self.classes.push(
+            declaration
+                .superclass
+                .as_ref()
+                .map(|(superclass, _)| superclass.clone()),
+        );
+
+        let mut members: Vec<&String> = vec![];
+
+        self.functions.push(FunctionScope {
+            kind: FunctionKind::Method,
+            locals: vec![],
+            upvalues: vec![],
+        });
+        self.scope_depth += 1;
+        self.declare("this", declaration.span, None, true);
+        for field in declaration.fields.iter() {
+            if members.contains(&&field.name) {
+                self.member_error(&field.name, class_name, field.span);
+            }
+            members.push(&field.name);
+
+            if let Some(initializer) = &field.initializer {
+                self.resolve_expression(initializer);
+            }
+        }
+        self.end_scope();
+        self.scope_depth -= 1;
+        let fields = self.functions.pop().unwrap();
+        self.resolution.upvalues.insert(
+            (declaration.span, "##FIELDS##".to_string()),
+            fields.upvalues,
+        );
+
+        for method in declaration.methods.iter() {
+            if members.contains(&&method.function.name) {
+                self.member_error(&method.function.name, class_name, method.function.span);
+            }
+            members.push(&method.function.name);
+
+            self.resolve_function(&method.function, FunctionKind::Method);
+        }
+        self.classes.pop();
+        self.define(class_name);
+    }
+
+    fn resolve_expression(&mut self, expression: &Expr) {
+        let span = expression.span;
+
+        match &expression.kind {
+            ExprKind::Nil
+            | ExprKind::Boolean(_)
+            | ExprKind::Int(_)
+            | ExprKind::BigInt(_)
+            | ExprKind::Number(_)
+            | ExprKind::String(_) => {}
+
+            ExprKind::Variable(name) => self.resolve_variable(name, span),
+
+            ExprKind::Assign { name, value } => {
+                self.resolve_expression(value);
+                self.bind(name, span);
+            }
+
+            ExprKind::This => {
+                if self.classes.is_empty() {
+                    self.errors.push(
+                        LoxError::new(
+                            "Cannot use this outside of a class",
+                            ErrorContext::Compile,
+                            Some(span),
+                        )
+                        .with_help("this is only available in methods and field initializers"),
+                    );
+                    return;
+                }
+                self.resolve_variable("this", span);
+            }
+
+            ExprKind::Super(_) => {
+                let superclass = match self.classes.last() {
+                    Some(Some(superclass)) => superclass.clone(),
+                    Some(None) => {
+                        return self.error(
+                            "Cannot use super in a class that does not expand another class",
+                            span,
+                        )
+                    }
+                    None => return self.error("Cannot use super outside of a class", span),
+                };
+                self.resolve_variable("this", span);
+                self.resolve_variable(&superclass, span);
+            }
+
+            ExprKind::Unary { operand, .. } => self.resolve_expression(operand),
+
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+
+            ExprKind::Call { callee, args } => {
+                self.resolve_expression(callee);
+                args.iter().for_each(|arg| self.resolve_expression(arg));
+            }
+
+            ExprKind::Get { object, .. } => self.resolve_expression(object),
+
+            ExprKind::Set { object, value, .. } => {
+                self.resolve_expression(object);
+                self.resolve_expression(value);
+            }
+
+            ExprKind::List(elements) => {
+                elements
+                    .iter()
+                    .for_each(|element| self.resolve_expression(element));
+            }
+
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+
+            ExprKind::Index { object, index } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+        }
+    }
+
+    fn resolve_variable(&mut self, name: &str, span: Span) {
+        let uninitialized = self
+            .function()
+            .locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .is_some_and(|local| !local.defined);
+        if uninitialized {
+            self.error(
+                format!("Cannot read local variable {} in its own initializer", name).as_str(),
+                span,
+            );
+        }
+
+        self.bind(name, span);
+    }
+
+    fn bind(&mut self, name: &str, span: Span) -> Binding {
+        let level = self.functions.len() - 1;
+        let binding = match self.resolve_local(level, name) {
+            Some(slot) => Binding::Local(slot),
+            None => match self.resolve_upvalue(level, name) {
+                Some(index) => Binding::Upvalue(index),
+                None => Binding::Global,
+            },
+        };
+
+        self.resolution
+            .bindings
+            .insert((span, name.to_string()), binding);
+        binding
+    }
+
+    fn resolve_local(&mut self, level: usize, name: &str) -> Option<usize> {
+        let slot = self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)?;
+        self.functions[level].locals[slot].used = true;
+        Some(slot)
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<usize> {
+        let enclosing = level.checked_sub(1)?;
+
+        let (is_local, index) = match self.resolve_local(enclosing, name) {
+            Some(slot) => {
+                let local = &self.functions[enclosing].locals[slot];
+                self.resolution
+                    .captured
+                    .insert((local.span, local.name.clone()));
+                (true, slot)
+            }
+            None => (false, self.resolve_upvalue(enclosing, name)?),
+        };
+
+        let upvalues = &mut self.functions[level].upvalues;
+        match upvalues
+            .iter()
+            .position(|upvalue| *upvalue == (is_local, index))
+        {
+            Some(address) => Some(address),
+            None => {
+                upvalues.push((is_local, index));
+                Some(upvalues.len() - 1)
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span, kind: Option<&'static str>, defined: bool) {
+        if name == "_" && kind != Some("parameter") {
+            return;
+        }
+
+        let depth = self.scope_depth;
+        if self
+            .function()
+            .locals
+            .iter()
+            .any(|local| local.name == name && local.depth == depth)
+        {
+            self.error(
+                format!("Variable {:?} is already defined", name).as_str(),
+                span,
+            );
+            return;
+        }
+
+        self.function().locals.push(Local {
+            kind,
+            span,
+            depth,
+            defined,
+            used: false,
+            name: name.to_string(),
+        });
+    }
+
+    fn define(&mut self, name: &str) {
+        match self.scope_depth {
+            0 => self.globals.push(name.to_string()),
+            _ => {
+                if let Some(local) = self
+                    .function()
+                    .locals
+                    .iter_mut()
+                    .rev()
+                    .find(|local| local.name == name)
+                {
+                    local.defined = true;
+                }
+            }
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let depth = self.scope_depth;
+        while let Some(local) = self.function().locals.last() {
+            if local.depth != depth {
+                break;
+            }
+            let local = self.function().locals.pop().unwrap();
+
+            if let Some(kind) = local.kind {
+                if !local.used && !local.name.starts_with('_') {
+                    self.warnings.push(
+                        LoxError::new(
+                            format!("Unused {} {}", kind, local.name).as_str(),
+                            ErrorContext::Warning,
+                            Some(local.span),
+                        )
+                        .with_help("prefix the name with an underscore to silence this warning"),
+                    );
+                }
+            }
+        }
+    }
+
+    fn member_error(&mut self, name: &String, class_name: &String, span: Span) {
+        self.error(
+            format!("Member {} is already defined in {}", name, class_name).as_str(),
+            span,
+        );
+    }
+
+    fn error(&mut self, message: &str, span: Span) {
+        self.errors
+            .push(LoxError::new(message, ErrorContext::Compile, Some(span)));
+    }
+
+    fn function(&mut self) -> &mut FunctionScope {
+        self.functions.last_mut().unwrap()
+    }
+}
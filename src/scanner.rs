@@ -1,4 +1,6 @@
-use crate::token::{Kind, Token};
+use num_bigint::BigInt;
+
+use crate::token::{Kind, Span, Token};
 use crate::value::Value;
 use std::iter::Peekable;
 use std::str::Chars;
@@ -23,7 +25,7 @@ impl<'a> Scanner<'a> {
 
     fn new_token(&mut self, kind: Kind, start: (usize, usize), proceed_by: usize) -> Option<Token> {
         self.cursor = (self.cursor.0, self.cursor.1 + proceed_by);
-        Some(Token::new(kind, start, None))
+        Some(Token::new(kind, Span::new(start, self.cursor), None))
     }
 }
 
@@ -42,15 +44,20 @@ impl Iterator for Scanner<'_> {
                 self.next()
             }
 
+            Some('#') => {
+                while self.source.peek().is_some() {
+                    if self.source.next().unwrap() == '\n' {
+                        self.cursor = (self.cursor.0 + 1, 1);
+                        break;
+                    }
+                }
+                self.next()
+            }
+
             Some('/') => match self.source.peek() {
                 Some('/') => {
-                    while self.source.peek().is_some() {
-                        if self.source.next().unwrap() == '\n' {
-                            self.cursor = (self.cursor.0 + 1, 1);
-                            break;
-                        }
-                    }
-                    self.next()
+                    self.source.next();
+                    self.new_token(Kind::SlashSlash, self.cursor, 2)
                 }
                 None | Some(_) => self.new_token(Kind::Slash, self.cursor, 1),
             },
@@ -60,12 +67,15 @@ impl Iterator for Scanner<'_> {
             Some(';') => self.new_token(Kind::Semicolon, self.cursor, 1),
             Some(',') => self.new_token(Kind::Comma, self.cursor, 1),
             Some('.') => self.new_token(Kind::Dot, self.cursor, 1),
+            Some(':') => self.new_token(Kind::Colon, self.cursor, 1),
             Some('+') => self.new_token(Kind::Plus, self.cursor, 1),
             Some('-') => self.new_token(Kind::Minus, self.cursor, 1),
             Some('*') => self.new_token(Kind::Star, self.cursor, 1),
             Some('%') => self.new_token(Kind::Percent, self.cursor, 1),
             Some('{') => self.new_token(Kind::LeftBrace, self.cursor, 1),
             Some('}') => self.new_token(Kind::RightBrace, self.cursor, 1),
+            Some('[') => self.new_token(Kind::LeftBracket, self.cursor, 1),
+            Some(']') => self.new_token(Kind::RightBracket, self.cursor, 1),
 
             Some('!') => match self.source.peek() {
                 Some('=') => {
@@ -74,13 +84,13 @@ impl Iterator for Scanner<'_> {
                 }
                 None => Some(Token::new(
                     Kind::Error,
-                    self.cursor,
+                    Span::new(self.cursor, self.cursor),
                     Some(Value::from("Unexpected end of script")),
                 )),
 
                 Some(character) => Some(Token::new(
                     Kind::Error,
-                    self.cursor,
+                    Span::new(self.cursor, (self.cursor.0, self.cursor.1 + 2)),
                     Some(Value::String(format!("Unexpected character {}", character))),
                 )),
             },
@@ -93,7 +103,7 @@ impl Iterator for Scanner<'_> {
                 Some(_) => self.new_token(Kind::Equal, self.cursor, 1),
                 None => Some(Token::new(
                     Kind::Error,
-                    self.cursor,
+                    Span::new(self.cursor, self.cursor),
                     Some(Value::from("Unexpected end of script")),
                 )),
             },
@@ -110,7 +120,7 @@ impl Iterator for Scanner<'_> {
                 Some(_) => self.new_token(Kind::Less, self.cursor, 1),
                 None => Some(Token::new(
                     Kind::Error,
-                    self.cursor,
+                    Span::new(self.cursor, self.cursor),
                     Some(Value::from("Unexpected end of script")),
                 )),
             },
@@ -123,7 +133,7 @@ impl Iterator for Scanner<'_> {
                 Some(_) => self.new_token(Kind::Greater, self.cursor, 1),
                 None => Some(Token::new(
                     Kind::Error,
-                    self.cursor,
+                    Span::new(self.cursor, self.cursor),
                     Some(Value::from("Unexpected end of script")),
                 )),
             },
@@ -137,7 +147,7 @@ impl Iterator for Scanner<'_> {
                     if peeked.is_none() {
                         return Some(Token::new(
                             Kind::Error,
-                            self.cursor,
+                            Span::new(self.token_start.unwrap(), self.cursor),
                             Some(Value::from("Unexpected end of script")),
                         ));
                     }
@@ -160,7 +170,7 @@ impl Iterator for Scanner<'_> {
 
                 let token = Token::new(
                     Kind::String,
-                    self.token_start.unwrap(),
+                    Span::new(self.token_start.unwrap(), self.cursor),
                     Some(Value::String(self.storage.clone())),
                 );
                 self.storage = String::new();
@@ -187,11 +197,22 @@ impl Iterator for Scanner<'_> {
                     self.source.next();
                 }
 
-                let token = Token::new(
-                    Kind::Number,
-                    self.token_start.unwrap(),
-                    Some(Value::Number(self.storage.parse().unwrap())),
-                );
+                let span = Span::new(self.token_start.unwrap(), self.cursor);
+                let token = match self.storage.contains('.') {
+                    true => Token::new(
+                        Kind::Number,
+                        span,
+                        Some(Value::Number(self.storage.parse().unwrap())),
+                    ),
+                    false => match self.storage.parse() {
+                        Ok(value) => Token::new(Kind::Number, span, Some(Value::Int(value))),
+                        Err(_) => Token::new(
+                            Kind::Number,
+                            span,
+                            Some(Value::from(self.storage.parse::<BigInt>().unwrap())),
+                        ),
+                    },
+                };
                 self.storage = String::new();
                 self.token_start = None;
                 Some(token)
@@ -219,6 +240,7 @@ impl Iterator for Scanner<'_> {
 
                 if let Some(character) = self.source.peek() {
                     if ['!', '?'].contains(character) {
+                        self.cursor = (self.cursor.0, self.cursor.1 + 1);
                         self.storage.push(*character);
                         self.source.next();
                     }
@@ -227,13 +249,13 @@ impl Iterator for Scanner<'_> {
                 let token = if let Some(keyword_kind) = Kind::keyword_equivalent(&self.storage) {
                     Token::new(
                         keyword_kind,
-                        self.token_start.unwrap(),
+                        Span::new(self.token_start.unwrap(), self.cursor),
                         Some(Value::String(self.storage.clone())),
                     )
                 } else {
                     Token::new(
                         Kind::Identifier,
-                        self.token_start.unwrap(),
+                        Span::new(self.token_start.unwrap(), self.cursor),
                         Some(Value::String(self.storage.clone())),
                     )
                 };
@@ -243,13 +265,62 @@ impl Iterator for Scanner<'_> {
                 Some(token)
             }
 
-            Some(character) => Some(Token::new(
-                Kind::Error,
-                self.cursor,
-                Some(Value::String(format!("Unexpected character {}", character))),
+            Some(character) => {
+                let start = self.cursor;
+                self.cursor = (self.cursor.0, self.cursor.1 + 1);
+                Some(Token::new(
+                    Kind::Error,
+                    Span::new(start, self.cursor),
+                    Some(Value::String(format!("Unexpected character {}", character))),
+                ))
+            }
+
+            None => Some(Token::new(
+                Kind::Eof,
+                Span::new(self.cursor, self.cursor),
+                None,
             )),
+        }
+    }
+}
+
+pub(crate) struct Tokens<'a> {
+    previous: Option<Token>,
+    scanner: Peekable<Scanner<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(source: &'a str) -> Tokens<'a> {
+        Tokens {
+            previous: None,
+            scanner: Scanner::new(source).peekable(),
+        }
+    }
+
+    pub(crate) fn peek(&mut self) -> Option<&Token> {
+        self.scanner.peek()
+    }
+
+    pub(crate) fn previous(&self) -> Span {
+        self.previous
+            .as_ref()
+            .map(|token| token.span())
+            .unwrap_or_default()
+    }
 
-            None => Some(Token::new(Kind::Eof, self.cursor, None)),
+    pub(crate) fn previous_kind(&self) -> Option<Kind> {
+        self.previous.as_ref().map(|token| token.kind())
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.scanner.next();
+        if token.is_some() {
+            self.previous = token.clone();
         }
+        token
     }
 }
@@ -1,6 +1,18 @@
 #[cfg(test)]
 mod test {
+    use std::rc::Rc;
+
+    use crate::bytecode::{deserialize, serialize};
+    use crate::closure::Closure;
+    use crate::compiler::Compiler;
+    use crate::disassembler::disassemble;
     use crate::error::InterpretResult;
+    use crate::function::Function;
+    use crate::op::OpCode;
+    use crate::scanner::Scanner;
+    use crate::token::Kind;
+    use crate::trace::{TraceLevel, Tracer};
+    use crate::value::Value;
     use crate::vm::VM;
 
     #[test]
@@ -221,28 +233,138 @@ mod test {
         assert_eq!(vm.stdout, vec!["2", "5", "1", "4", "0", "3",]);
     }
 
-    // #[test]
-    // fn local_while() {
-    //     let mut vm = VM::new();
-    //     assert_eq!(
-    //         vm.interpret(
-    //             r#"
-    //                 {
-    //                     let a = 2;
-    //                     let b = 5;
-    //                     while a * b != -2 {
-    //                         print(a, b);
-    //                         a = a - 1;
-    //                         b = b - 1;
-    //                     }
-    //                 }
-    //             "#
-    //             .to_string()
-    //         ),
-    //         InterpretResult::Ok
-    //     );
-    //     assert_eq!(vm.stdout, vec!["2", "5", "1", "4", "0", "3",]);
-    // }
+    #[test]
+    fn global_for() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let total = 0;
+                    for let i = 1; i <= 4; i = i + 1 {
+                        let double = i * 2;
+                        total = total + double;
+                    }
+                    print(total);
+
+                    let j = 0;
+                    for ; j < 2; {
+                        print(j);
+                        j = j + 1;
+                    }
+                    for j = 5; j < 7; j = j + 1 print(j);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["20", "0", "1", "5", "6"]);
+    }
+
+    #[test]
+    fn for_variable_scope() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    for let i = 0; i < 2; i = i + 1 {
+                        print(i);
+                    }
+                    print(i);
+                "#
+                .to_string()
+            ),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(vm.stdout, vec!["0", "1"]);
+    }
+
+    #[test]
+    fn closure_in_for() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    for let i = 0; i < 3; i = i + 1 {
+                        fun show() { print(i); }
+                        show();
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn local_while() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    {
+                        let a = 2;
+                        let b = 5;
+                        while a * b != -2 {
+                            print(a, b);
+                            a = a - 1;
+                            b = b - 1;
+                        }
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["2", "5", "1", "4", "0", "3",]);
+    }
+
+    #[test]
+    fn local_for() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun count(n) {
+                        let total = 0;
+                        for let i = 0; i < n; i = i + 1 {
+                            let square = i * i;
+                            total = total + square;
+                        }
+                        return total;
+                    }
+                    print(count(4), count(3));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["14", "5"]);
+    }
+
+    #[test]
+    fn long_loop() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun find(limit) {
+                        let i = 0;
+                        while true {
+                            if i * i > limit {
+                                return i;
+                            }
+                            i = i + 1;
+                        }
+                    }
+                    print(find(100000000));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["10001"]);
+    }
 
     #[test]
     fn parse_test() {
@@ -259,7 +381,7 @@ mod test {
             ),
             InterpretResult::Ok
         );
-        assert_eq!(vm.stdout, vec!["30", "4", "4.5", "false"]);
+        assert_eq!(vm.stdout, vec!["30", "4.0", "4.5", "false"]);
     }
 
     #[test]
@@ -309,49 +431,1459 @@ mod test {
         assert_eq!(vm.stdout, vec!["U-235", "\n"]);
     }
 
-    // #[test]
-    // fn return_in_while() {
-    //     let mut vm = VM::new();
-    //     assert_eq!(
-    //         vm.interpret(
-    //             r#"
-    //                 fun make_closure() {
-    //                     while true {
-    //                         let i = "i";
-    //                         fun show() print(i);
-    //                         return show;
-    //                     }
-    //                 }
-    //                 let closure = make_closure();
-    //                 closure();
-    //             "#
-    //             .to_string()
-    //         ),
-    //         InterpretResult::Ok
-    //     );
-    //     assert_eq!(vm.stdout, vec!["i"]);
-    // }
-
-    // #[test]
-    // fn closure_in_while() {
-    //     let mut vm = VM::new();
-    //     assert_eq!(
-    //         vm.interpret(
-    //             r#"
-    //                 fun make_closures() {
-    //                     let i = 5;
-    //                     while i {
-    //                         fun closure() { print(i); }
-    //                         i = i - 1;
-    //                         closure();
-    //                     }
-    //                 }
-    //                 make_closures();
-    //             "#
-    //             .to_string()
-    //         ),
-    //         InterpretResult::Ok
-    //     );
-    //     assert_eq!(vm.stdout, vec!["5", "4", "3", "2", "1"]);
-    // }
+    #[test]
+    fn class_fields_and_methods() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class Point {
+                        pub x = 0;
+                        pub y = this.x + 1;
+
+                        pub fun init(x) {
+                            this.x = x;
+                        }
+
+                        pub fun sum() {
+                            return this.x + this.y;
+                        }
+                    }
+                    let p = Point(5);
+                    print(p.x, p.y, p.sum());
+                    p.y = 10;
+                    print(p.sum(), p);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["5", "1", "6", "15", "Point instance"]);
+    }
+
+    #[test]
+    fn class_inheritance() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class Animal {
+                        pub name = "animal";
+
+                        pub fun speak() {
+                            return this.name <> " makes a sound";
+                        }
+                    }
+
+                    class Dog expands Animal {
+                        pub fun init(name) {
+                            this.name = name;
+                        }
+
+                        pub fun speak() {
+                            return super.speak() <> ", woof";
+                        }
+                    }
+                    let dog = Dog("rex");
+                    let speak = dog.speak;
+                    print(Animal().speak(), speak());
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout,
+            vec!["animal makes a sound", "rex makes a sound, woof"]
+        );
+    }
+
+    #[test]
+    fn private_members() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class Counter {
+                        count = 0;
+
+                        fun step() {
+                            this.count = this.count + 1;
+                        }
+
+                        pub fun next() {
+                            this.step();
+                            return this.count;
+                        }
+                    }
+                    let counter = Counter();
+                    counter.next();
+                    print(counter.next());
+                    print(counter.count);
+                "#
+                .to_string()
+            ),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(vm.stdout, vec!["2"]);
+
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class Counter {
+                        fun step() {}
+                    }
+                    Counter().step();
+                "#
+                .to_string()
+            ),
+            InterpretResult::RuntimeError
+        );
+    }
+
+    #[test]
+    fn invalid_class_constructs() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret("print(this);".to_string()),
+            InterpretResult::CompileError
+        );
+
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class A {
+                        pub fun a() { return super.a(); }
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class A {
+                        pub fun a() {}
+                        pub x;
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+    }
+
+    #[test]
+    fn return_in_while() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun make_closure() {
+                        while true {
+                            let i = "i";
+                            fun show() print(i);
+                            return show;
+                        }
+                    }
+                    let closure = make_closure();
+                    closure();
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["i"]);
+    }
+
+    #[test]
+    fn closure_in_while() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun make_closures() {
+                        let i = 5;
+                        while i {
+                            fun closure() { print(i); }
+                            i = i - 1;
+                            closure();
+                        }
+                    }
+                    make_closures();
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["4", "3", "2", "1", "0"]);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun count(n) {
+                        if n == 0 {
+                            return 0;
+                        }
+                        return 1 + count(n - 1);
+                    }
+                    print(count(100000));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["100000"]);
+    }
+
+    #[test]
+    fn captured_assignment() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun make_counter() {
+                        let count = 0;
+                        fun increment() {
+                            count = count + 1;
+                            return count;
+                        }
+                        fun get() {
+                            return count;
+                        }
+                        increment();
+                        increment();
+                        print(get());
+                        return increment;
+                    }
+                    let counter = make_counter();
+                    print(counter(), counter());
+                    {
+                        let x = 1;
+                        fun bump() { x = x * 10; }
+                        bump();
+                        print(x);
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["2", "3", "4", "10"]);
+    }
+
+    #[test]
+    fn closure_per_call() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun make_adder(n) {
+                        fun add(x) {
+                            return x + n;
+                        }
+                        return add;
+                    }
+                    let add_one = make_adder(1);
+                    let add_two = make_adder(2);
+                    print(add_one(10), add_two(10), add_one == add_two);
+
+                    let first = nil;
+                    let second = nil;
+                    for let i = 0; i < 2; i = i + 1 {
+                        fun show() { return i; }
+                        if i == 0 {
+                            first = show;
+                        } else {
+                            second = show;
+                        }
+                    }
+                    print(first(), second());
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["11", "12", "false", "0", "1"]);
+    }
+
+    #[test]
+    fn call_values() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun make_adder(n) {
+                        fun add(x) { return x + n; }
+                        return add;
+                    }
+                    print(make_adder(1)(2), (make_adder)(3)(4));
+
+                    let show = print;
+                    show("shown");
+
+                    fun outer() {
+                        fun helper() { return "inner"; }
+                        return helper();
+                    }
+                    fun helper() { return "outer"; }
+                    print(outer(), helper());
+
+                    class Box { pub callback = nil; }
+                    let box = Box();
+                    box.callback = make_adder(10);
+                    print(box.callback(5));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["3", "7", "shown", "inner", "outer", "15"]);
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun inner() {
+                        return 1 + "a";
+                    }
+                    fun outer() {
+                        return inner();
+                    }
+                    outer();
+                "#
+                .to_string()
+            ),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.interpret("print(missing);".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.interpret("fun f(_a) {} f(1, 2);".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.interpret("class A { secret = 1; } A().secret;".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.interpret("let x = 1; x();".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: Operands of + must be numbers, got int and string at 3:34\n    [3:34] in inner()\n    [6:37] in outer()\n    [8:26] in script",
+                "Runtime error: Undefined variable missing at 1:7\n    [1:7] in script",
+                "Runtime error: f expects 1 arguments but got 2 at 1:15\n    [1:15] in script",
+                "Runtime error: secret of A is private at 1:29\n    [1:29] in script",
+                "Runtime error: Can only call functions and classes, got int at 1:13\n    [1:13] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_error_locations() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun f() {
+                        let a = 1;
+                        let a = 2;
+                    }
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.interpret("class A expands A {}".to_string()),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Warning: Unused variable a at 3:29",
+                "Compile error: Variable \"a\" is already defined at 4:29",
+                "Compile error: Class A cannot expand itself at 1:17",
+            ]
+        );
+    }
+
+    #[test]
+    fn rich_diagnostics() {
+        let mut vm = VM::new();
+        vm.set_file("main.lox");
+        assert_eq!(
+            vm.interpret("fun f() {\n    print(this);\n}\n".to_string()),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.interpret("fun f() {\n    return -nil;\n}\nf();\n".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Compile error: Cannot use this outside of a class\n --> main.lox:2:11\n  |\n2 |     print(this);\n  |           ^^^^\n  = help: this is only available in methods and field initializers",
+                "Runtime error: Operand of - must be a number, got nil\n --> main.lox:2:13\n  |\n2 |     return -nil;\n  |             ^^^\n  = trace: [2:13] in f()\n           [4:2] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error_recovery() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let a = ;
+                    print(a b);
+                    fun f(x y) { return x; }
+                    let ok = 1 +;
+                    while ok { let = 2; }
+                    print(1
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Compile error: Unexpected `;` at 2:29",
+                "Compile error: Unexpected identifier b at 3:29",
+                "Compile error: Unexpected identifier y at 4:29",
+                "Compile error: Unexpected `;` at 5:33",
+                "Compile error: Unexpected `=` at 6:36",
+                "Compile error: Unexpected end of script at 8:17",
+            ]
+        );
+    }
+    #[test]
+    fn operator_precedence() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    print(1 + 2 == 3, " ");
+                    print(2 + 3 * 4, " ");
+                    print(10 - 2 - 3, " ");
+                    print(1 < 2 == true, " ");
+                    print(-2 * 3 <> "!", " ");
+                    print(nil or 1 and 2);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout,
+            vec!["true", " ", "14", " ", "5", " ", "true", " ", "-6!", " ", "2"]
+        );
+    }
+
+    #[test]
+    fn resolver() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun outer(unused, _ignored) {
+                        let a = 1;
+                        fun inner() {
+                            a = a + 1;
+                            return a;
+                        }
+                        let b = 2;
+                        return inner;
+                    }
+                    println(outer(1, 2)());
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout, vec!["2", "\n"]);
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Warning: Unused parameter unused at 2:31",
+                "Warning: Unused variable b at 8:29",
+            ]
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    {
+                        let a = a;
+                    }
+                    return 1;
+                    println(this);
+                    fun f(x, x) { return x; }
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Compile error: Cannot read local variable a in its own initializer at 3:33",
+                "Compile error: Cannot return from top-level code at 5:21",
+                "Compile error: Cannot use this outside of a class at 6:29",
+                "Compile error: Variable \"x\" is already defined at 7:30",
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_folding() {
+        let source = r#"
+            let day = 60 * 60 * 24;
+            if false {
+                println("unreachable");
+            } else {
+                println(day <> " " <> "a" <> "b");
+            }
+            while nil println("never");
+            println(not "" and 10 - 4 > 5, " ", 7 % 4 == 3 or missing);
+        "#;
+
+        let mut sizes = vec![];
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+            assert_eq!(vm.stdout, vec!["86400 ab", "\n", "true", " ", "true", "\n"]);
+
+            let main_function = Function::new_main("##MAIN##".to_string());
+            let function = Compiler::new(&mut vm, main_function, source).compile();
+            sizes.push(function.unwrap().size());
+        }
+        assert!(sizes[0] < sizes[1] / 2);
+    }
+
+    #[test]
+    fn peephole() {
+        let source = r#"
+            let ready = false;
+            if not ready or ready {
+                let total = 0;
+                let i = 0;
+                while not (i >= 5) {
+                    total = total + i;
+                    i = i - 1 + 2;
+                }
+                println(total, " ", nil or "fallback", " ", not ready and "done");
+            }
+        "#;
+
+        let mut listings = vec![];
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+            assert_eq!(vm.stdout, vec!["10", " ", "fallback", " ", "done", "\n"]);
+
+            let main_function = Function::new_main("##MAIN##".to_string());
+            let function = Compiler::new(&mut vm, main_function, source).compile();
+            listings.push(format!("{:?}", function.unwrap()));
+        }
+
+        for op in ["AddConstant", "SetLocalPop", "JumpIfTrue"] {
+            assert!(listings[0].contains(op));
+            assert!(!listings[1].contains(op));
+        }
+        assert_eq!(listings[0].matches("Not\n").count(), 2);
+        assert_eq!(listings[1].matches("Not\n").count(), 3);
+    }
+
+    #[test]
+    fn bytecode_encoding() {
+        let mut vm = VM::new();
+        let main_function = Function::new_main("##MAIN##".to_string());
+        let function = Compiler::new(&mut vm, main_function, "let a = 1;").compile();
+        assert_eq!(function.unwrap().size(), 6);
+
+        let locals: Vec<String> = (0..300)
+            .map(|index| format!("let l{} = {};", index, index))
+            .collect();
+        let source = format!("{{ {} println(l299 + l0, l256); }}", locals.join(" "));
+        let mut vm = VM::new();
+        assert_eq!(vm.interpret(source), InterpretResult::Ok);
+        assert_eq!(vm.stdout, vec!["299", "256", "\n"]);
+    }
+
+    #[test]
+    fn constant_pools() {
+        let mut vm = VM::new();
+        vm.set_optimize(false);
+        let main_function = Function::new_main("##MAIN##".to_string());
+        let source = r#"let a = 1; println(a, 1, "a", 2, "a", a, -0.0, 0.0);"#;
+        let function = Compiler::new(&mut vm, main_function, source)
+            .compile()
+            .unwrap();
+
+        let mut operands = vec![];
+        let mut offset = 0;
+        while let Some((op, instruction_operands, next)) = function.instruction(offset) {
+            if matches!(op, OpCode::Constant | OpCode::DefGlobal | OpCode::GetGlobal) {
+                operands.push(instruction_operands[0]);
+            }
+            offset = next;
+        }
+        assert_eq!(operands, vec![0, 1, 2, 1, 0, 1, 3, 1, 1, 4, 4]);
+
+        assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+        assert_eq!(
+            vm.stdout,
+            vec!["1", "1", "a", "2", "a", "1", "-0.0", "0.0", "\n"]
+        );
+    }
+
+    #[test]
+    fn bytecode_files() {
+        let mut vm = VM::new();
+        let function = vm
+            .compile(
+                r#"
+                    class A {
+                        pub x = 40;
+                        pub fun get(y) { return this.x + y; }
+                    }
+                    fun counter() {
+                        let n = 0;
+                        fun inc() { n = n + 1; return n; }
+                        return inc;
+                    }
+                    let c = counter();
+                    c();
+                    println(A().get(2), " ", c(), " ", "done");
+                "#
+                .to_string(),
+            )
+            .unwrap();
+        let bytes = serialize(&function);
+        assert_eq!(&bytes[..4], b"LOXC");
+
+        let mut vm = VM::new();
+        assert_eq!(vm.run(deserialize(&bytes).unwrap()), InterpretResult::Ok);
+        assert_eq!(vm.stdout, vec!["42", " ", "2", " ", "done", "\n"]);
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[5] = 7;
+        let mut corrupt = bytes.clone();
+        corrupt[20] ^= 0xff;
+        let errors: Vec<String> = [
+            &b"#!/bin/lox"[..],
+            &wrong_version,
+            &corrupt,
+            &bytes[..bytes.len() - 1],
+        ]
+        .iter()
+        .map(|bytes| deserialize(bytes).unwrap_err().to_string())
+        .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Bytecode error: Not a compiled Lox file",
+                "Bytecode error: Unsupported bytecode version 7, expected 6",
+                "Bytecode error: Checksum mismatch, the file is corrupt",
+                "Bytecode error: Checksum mismatch, the file is corrupt",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembler() {
+        let source = r#"let greeting = "hi";
+fun twice(n) {
+    let i = 0;
+    while i < n { i = i + 1; }
+    return i;
+}
+class A { pub fun get() { return greeting; } }"#;
+        let mut vm = VM::new();
+        let function = vm.compile(source.to_string()).unwrap();
+        let listing = disassemble(&function, Some(source));
+        let lines: Vec<&str> = listing.lines().map(str::trim).collect();
+
+        assert_eq!(lines[0], "== script ==");
+        assert_eq!(lines[1], r#"1 | let greeting = "hi";"#);
+        assert_eq!(lines[2], r#"0000  Constant          0  ; "hi""#);
+        assert!(lines.contains(&"0006  MakeClosure       2  ; <fun twice/1>"));
+        assert!(lines.contains(&"== twice/1 =="));
+        assert!(lines.contains(&"4 | while i < n { i = i + 1; }"));
+        assert!(lines.contains(&"0008  JumpIfFalse       11  -> 0022"));
+        assert!(lines.contains(&"0019  Loop              19  -> 0003"));
+        assert!(lines.contains(&"== A.get/0 =="));
+        assert!(lines.contains(&r#"0000  GetGlobal         0  ; "greeting""#));
+    }
+
+    #[test]
+    fn tracing() {
+        let source = r#"
+            fun add(a, b) { return a + b; }
+            println(add(1, 2));
+        "#;
+        let path = std::env::temp_dir().join(format!("lox-trace-{}.txt", std::process::id()));
+        let trace = |tracer: Tracer| {
+            let mut vm = VM::new();
+            vm.set_tracer(Some(tracer.write_to(path.to_str().unwrap()).unwrap()));
+            assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+            assert_eq!(vm.stdout, vec!["3", "\n"]);
+            std::fs::read_to_string(&path).unwrap()
+        };
+
+        let ops = trace(Tracer::new(TraceLevel::Ops));
+        assert!(ops
+            .lines()
+            .any(|line| line == "  1 script       0000  MakeClosure 0"));
+        assert!(ops.lines().any(|line| line == "  2 add          0004  Add"));
+
+        let stack = trace(Tracer::new(TraceLevel::Stack).only("add"));
+        assert_eq!(
+            stack.lines().map(str::trim_end).collect::<Vec<_>>(),
+            vec![
+                "  2 add          0000  GetLocal 0       | 2",
+                "  2 add          0002  GetLocal 1       | 1",
+                "  2 add          0004  Add              | 2",
+                "  2 add          0005  Return           | 3",
+            ]
+        );
+
+        let frame = trace(Tracer::new(TraceLevel::Frame).only("add"));
+        assert!(frame
+            .lines()
+            .any(|line| line.ends_with("Add              | [1, 2, 1, 2]")));
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        let tracer = Tracer::new(TraceLevel::Ops).only("add");
+        vm.set_tracer(Some(tracer.write_to(path.to_str().unwrap()).unwrap()));
+        assert_eq!(
+            vm.interpret("fun add(a, b) { return a + b; }\nadd(1, nil);".to_string()),
+            InterpretResult::RuntimeError
+        );
+        let failed = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(failed.lines().last(), Some("  2 add          0004  Add"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lists() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let xs = [1, 2, "three", [4],];
+                    xs[0] = xs[-1][0] * 10;
+                    push(xs, nil);
+                    insert(xs, 1, "x");
+                    println(xs, " ", len(xs), " ", len("héllo"));
+                    println(pop(xs), " ", remove(xs, -2), " ", xs);
+                    println(slice(xs, 1), " ", slice(xs, -2, 10), " ", slice(xs, 3, 1));
+                    reverse(xs);
+                    println(xs, " ", contains(xs, "x"), " ", contains(xs, "three"));
+                    if [] { println("full"); } else { println("empty"); }
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "[40, \"x\", 2, \"three\", [4], nil] 6 5\n",
+                "nil three [40, \"x\", 2, [4]]\n",
+                "[\"x\", 2, [4]] [2, [4]] []\n",
+                "[[4], 2, \"x\", 40] true false\n",
+                "empty\n",
+            )
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        for source in [
+            "[1, 2][2];",
+            "let xs = [1]; xs[-2] = 0;",
+            "[1][0.5];",
+            "let n = 1; n[0];",
+            "pop([]);",
+            "push(nil, 1);",
+        ] {
+            assert_eq!(
+                vm.interpret(source.to_string()),
+                InterpretResult::RuntimeError
+            );
+        }
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: List index 2 is out of range for a list of length 2 at 1:7\n    [1:7] in script",
+                "Runtime error: List index -2 is out of range for a list of length 1 at 1:17\n    [1:17] in script",
+                "Runtime error: List index must be an integer, got 0.5 at 1:4\n    [1:4] in script",
+                "Runtime error: Only lists and maps can be indexed, got int at 1:13\n    [1:13] in script",
+                "Runtime error: pop from an empty list at 1:4\n    [1:4] in script",
+                "Runtime error: push expects a list, got nil at 1:5\n    [1:5] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let m = {"a": 1, 2: [3], true: nil, nil: "n",};
+                    m["a"] = m["a"] + 1;
+                    m[-0] = "zero";
+                    println(m, " ", len(m), " ", m[0], " ", m[2][0]);
+                    println(has(m, "a"), " ", has(m, "z"), " ", delete(m, 2), " ", delete(m, 2));
+                    println(keys(m), " ", values(m));
+                    println(entries({"x": {}}));
+                    let nan = {};
+                    nan[0 / 0] = "nan";
+                    nan[0 / 0] = "still nan";
+                    println(nan, " ", len(nan), " ", 0 == -0, " ", nan[0 / 0], " ", has(nan, 0 / 0));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "{\"a\": 2, 2: [3], true: nil, nil: \"n\", 0: \"zero\"} 5 zero 3\n",
+                "true false [3] nil\n",
+                "[\"a\", true, nil, 0] [2, nil, \"n\", \"zero\"]\n",
+                "[[\"x\", {}]]\n",
+                "{NaN: \"still nan\"} 1 true still nan true\n",
+            )
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        for source in [
+            r#"println({"a": 1}["b"]);"#,
+            "let m = {}; m[[1]] = 2;",
+            "has([], 1);",
+        ] {
+            assert_eq!(
+                vm.interpret(source.to_string()),
+                InterpretResult::RuntimeError
+            );
+        }
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: Key \"b\" is not in the map at 1:17\n    [1:17] in script",
+                "Runtime error: Map keys must be numbers, strings, booleans or nil, got list at 1:14\n    [1:14] in script",
+                "Runtime error: has expects a map, got list at 1:4\n    [1:4] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn equality_and_ordering() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class A { pub fun m() { return 1; } }
+                    let a = A();
+                    let xs = [1];
+                    let nan = 0 / 0;
+                    println(1 == 1.0000000000000002, " ", 0 == -0, " ", 0 / 0 == 0 / 0, " ", nan != nan);
+                    println(xs == xs, " ", [1] == [1], " ", {} == {}, " ", nil == false);
+                    println(a.m == a.m, " ", a.m == A().m, " ", println == println);
+                    println("a" < "b", " ", false < true, " ", 2 >= 2);
+                    println(compare(nil, "a"), compare(2, 1), compare("b", "b"), compare(true, 0));
+                    println(compare([1, 2], [1, 3]), compare([1], [1, 0]), compare(0 / 0, 1));
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "false true false true\n",
+                "true false false false\n",
+                "true false true\n",
+                "true true true\n",
+                "-110-1\n",
+                "-1-11\n",
+            )
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        for source in ["nil < \"a\";", "let n = 1; n >= \"1\";", "compare({}, {});"] {
+            assert_eq!(
+                vm.interpret(source.to_string()),
+                InterpretResult::RuntimeError
+            );
+        }
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: Operands of < must be two numbers, two strings or two booleans, got nil and string at 1:5\n    [1:5] in script",
+                "Runtime error: Operands of >= must be two numbers, two strings or two booleans, got int and string at 1:14\n    [1:14] in script",
+                "Runtime error: compare cannot order two map values at 1:8\n    [1:8] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn integers() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    # ints stay exact past 2^53
+                    println(1, " ", 1.0, " ", 9007199254740993 + 2, " ", type_of(1), " ", type_of(1.0));
+                    println(7 / 2, " ", 6 / 2, " ", 7 // 2, " ", -7 // 2, " ", 7.5 // 2);
+                    println(-7 % 3, " ", 7 % -3, " ", -7.5 % 2, " ", 1 + 0.5, " ", 2 * 1.5);
+                    println(1 == 1.0, " ", 9007199254740993 == 9007199254740992.0, " ", 2 < 2.5);
+                    println({1: "one"}[1.0], " ", div(-7, 2), " ", parse("12") + 1, " ", len([1]) + 1);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "1 1.0 9007199254740995 int float\n",
+                "3.5 3.0 3 -4 3.0\n",
+                "2 -2 0.5 1.5 3.0\n",
+                "true false true\n",
+                "one -4 13 2\n",
+            )
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        for source in ["println(5 // 0);", "println(5 % 0);"] {
+            assert_ne!(vm.interpret(source.to_string()), InterpretResult::Ok);
+        }
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: Integer division by zero at 1:11\n    [1:11] in script",
+                "Runtime error: Integer division by zero at 1:11\n    [1:11] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn big_integers() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun factorial(n) {
+                        let result = 1;
+                        for let i = 2; i <= n; i = i + 1 { result = result * i; }
+                        return result;
+                    }
+                    let big = factorial(25);
+                    println(big, " ", type_of(big), " ", big // factorial(23), " ", big % 1000000007);
+                    let max = 9223372036854775807;
+                    println(max + 1, " ", -(-max - 1), " ", max + 1 - 1 == max, " ", (max + 1) // -1);
+                    println(99999999999999999999, " ", parse("-123456789012345678901") * 2);
+                    println(max + 1 == 9223372036854775808.0, " ", big > 10000000000000000000000000.0, " ", big < max, " ", big / 10);
+                    println({max + 1: "key"}[9223372036854775808.0], " ", compare(max + 1, max), " ", [1, 2][max + 1 - max]);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "15511210043330985984000000 int 600 440732388\n",
+                "9223372036854775808 9223372036854775808 true -9223372036854775808\n",
+                "99999999999999999999 -246913578024691357802\n",
+                "true true false 1.5511210043330987e24\n",
+                "key 1 2\n",
+            )
+        );
+    }
+
+    #[test]
+    fn class_captures() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun f() {
+                        let x = 1;
+                        class A {
+                            pub fun g() {
+                                return x;
+                            }
+                        }
+                        return A;
+                    }
+                    println(f()().g());
+
+                    fun make(n) {
+                        let step = n * 2;
+                        class Counter {
+                            pub count = step;
+
+                            pub fun bump() {
+                                step = step + 1;
+                                return this.count + step;
+                            }
+                        }
+                        return Counter();
+                    }
+                    let counter = make(5);
+                    println(counter.count, " ", counter.bump(), " ", counter.bump());
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout.concat(), "1\n10 21 22\n");
+    }
+
+    #[test]
+    fn stack_overflow_trace() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret("fun f(n){return f(n+1);} f(0);".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![concat!(
+                "Runtime error: Stack overflow at 1:18\n",
+                "    [1:18] in f()\n",
+                "    ... 1048574 more frames in f()\n",
+                "    [1:27] in script",
+            )]
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret("fun f(n){return g(n);}\nfun g(n){return f(n+1);}\nf(0);".to_string()),
+            InterpretResult::RuntimeError
+        );
+        let trace: Vec<&str> = vm.stderr[0].lines().skip(1).collect();
+        assert_eq!(trace.len(), 21);
+        assert_eq!(trace[10], "    ... 1048556 more frames");
+        assert_eq!(trace[20], "    [3:2] in script");
+    }
+
+    #[test]
+    fn class_syntax_error_recovery() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    class X { pub fun }
+                    let q = ;
+                    class Y {
+                        a = ;
+                        pub fun 1() {}
+                        pub fun ok() {}
+                        b;
+                    }
+                    print(1 2);
+                "#
+                .to_string()
+            ),
+            InterpretResult::CompileError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Compile error: Expected identifier, got `}` at 2:39",
+                "Compile error: Unexpected `;` at 3:29",
+                "Compile error: Unexpected `;` at 5:29",
+                "Compile error: Expected identifier, got number 1 at 6:33",
+                "Compile error: Fields must be declared before methods at 8:25",
+                "Compile error: Unexpected number 2 at 10:29",
+            ]
+        );
+    }
+
+    #[test]
+    fn peephole_many_branches() {
+        let branches: String = (0..3000)
+            .map(|i| format!("if x == {} {{ x = x + 1; }} else {{ x = x - 1; }}\n", i))
+            .collect();
+        let source = format!("fun f(x) {{\n{}return x;\n}}\nprintln(f(3));", branches);
+
+        let mut vm = VM::new();
+        let start = std::time::Instant::now();
+        assert_eq!(vm.interpret(source), InterpretResult::Ok);
+        assert!(start.elapsed().as_secs() < 10);
+        assert_eq!(vm.stdout.concat(), "-2997\n");
+    }
+
+    #[test]
+    fn wide_jumps() {
+        let body = "x = x + 1;".repeat(20000);
+        let source = format!(
+            "let x = 0;\nif x == 0 {{ {} }} else {{ x = -1; }}\nlet i = 0;\nwhile i < 2 {{ {} i = i + 1; }}\nprintln(x);",
+            body, body
+        );
+
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            assert_eq!(vm.interpret(source.clone()), InterpretResult::Ok);
+            assert_eq!(vm.stdout.concat(), "60000\n");
+        }
+    }
+
+    #[test]
+    fn crafted_bytecode() {
+        let script = |build: &dyn Fn(&mut Function)| {
+            let mut function = Function::new_main("##MAIN##".to_string());
+            build(&mut function);
+            function.add_op(OpCode::Nil);
+            function.add_op(OpCode::Return);
+            serialize(&function)
+        };
+
+        let into_instruction = script(&|function| {
+            let address = function.add_constant(Value::Nil);
+            function.add_instruction(OpCode::Jump, &[1]);
+            function.add_instruction(OpCode::Constant, &[address]);
+        });
+        let captured = script(&|function| {
+            function.add_instruction(OpCode::GetCaptured, &[0]);
+        });
+        let closure = script(&|function| {
+            let mut inner = Function::new("inner".to_string(), 0);
+            inner.add_upvalue(false, 3);
+            let address = function.add_constant(Value::Function(Rc::new(Closure::new(
+                Rc::new(inner),
+                vec![],
+            ))));
+            function.add_instruction(OpCode::MakeClosure, &[address]);
+        });
+        let errors: Vec<String> = [into_instruction, captured, closure]
+            .iter()
+            .map(|bytes| deserialize(bytes).unwrap_err().to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Bytecode error: Corrupt bytecode file: jump into an instruction at 0 in ##MAIN##/0",
+                "Bytecode error: Corrupt bytecode file: invalid operand at 0 in ##MAIN##/0",
+                "Bytecode error: Corrupt bytecode file: invalid operand at 0 in ##MAIN##/0",
+            ]
+        );
+
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        for bytes in [
+            script(&|function| function.add_op(OpCode::CloseCaptured)),
+            script(&|function| {
+                function.add_op(OpCode::Nil);
+                function.add_instruction(OpCode::SetLocalPop, &[7]);
+            }),
+            script(&|function| {
+                function.add_instruction(OpCode::GetLocal, &[9]);
+            }),
+        ] {
+            let function = deserialize(&bytes).unwrap();
+            assert_eq!(vm.run(function), InterpretResult::RuntimeError);
+        }
+        assert_eq!(
+            vm.stderr,
+            vec![
+                "Runtime error: Stack underflow at 0:0\n    [0:0] in script",
+                "Runtime error: Invalid local variable at 0:0\n    [0:0] in script",
+                "Runtime error: Invalid local variable at 0:0\n    [0:0] in script",
+            ]
+        );
+    }
+
+    #[test]
+    fn cyclic_collections() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let xs = [1];
+                    push(xs, xs);
+                    println(xs);
+                    let m = {"a": 1};
+                    m["self"] = m;
+                    m["l"] = [m, xs];
+                    println(m);
+                    let y = [2];
+                    println([y, y]);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            concat!(
+                "[1, [...]]\n",
+                "{\"a\": 1, \"self\": {...}, \"l\": [{...}, [1, [...]]]}\n",
+                "[[2], [2]]\n",
+            )
+        );
+    }
+
+    #[test]
+    fn not_collections() {
+        let source = r#"
+            let xs = [];
+            println(not [], " ", not {}, " ", not [1], " ", not {1: 2});
+            if not xs { println("empty"); }
+            let b = not xs;
+            while not xs { push(xs, b); }
+            println(xs);
+            println(not println);
+        "#;
+
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+            assert_eq!(
+                vm.stdout.concat(),
+                "true true false false\nempty\n[true]\nfalse\n"
+            );
+        }
+    }
+
+    #[test]
+    fn slash_slash_is_not_a_comment() {
+        let kinds: Vec<Kind> = Scanner::new("// x\n# y\n7 // 2")
+            .map(|token| token.kind())
+            .take_while(|kind| *kind != Kind::Eof)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Kind::SlashSlash,
+                Kind::Identifier,
+                Kind::Number,
+                Kind::SlashSlash,
+                Kind::Number,
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_recursive_function() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    fun outer() {
+                        fun fact(n) {
+                            if n < 2 { return 1; }
+                            return n * fact(n - 1);
+                        }
+                        return fact(5);
+                    }
+                    println(outer());
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout.concat(), "120\n");
+    }
+
+    #[test]
+    fn local_class_refers_to_itself() {
+        let source = r#"
+            fun build() {
+                class Node {
+                    pub value;
+                    pub next = nil;
+
+                    pub fun prepend(value) {
+                        let node = Node();
+                        node.value = value;
+                        node.next = this;
+                        return node;
+                    }
+                }
+
+                let list = Node();
+                list.value = 1;
+                return list.prepend(2).prepend(3);
+            }
+
+            let node = build();
+            while node {
+                print(node.value, " ");
+                node = node.next;
+            }
+            println(not node, " ", not build());
+        "#;
+
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            assert_eq!(vm.interpret(source.to_string()), InterpretResult::Ok);
+            assert_eq!(vm.stdout.concat(), "3 2 1 true false\n");
+        }
+    }
+
+    #[test]
+    fn subtract_operands() {
+        for optimize in [true, false] {
+            let mut vm = VM::new();
+            vm.set_optimize(optimize);
+            vm.set_plain_diagnostics(true);
+            assert_eq!(
+                vm.interpret(
+                    "let x = 10;\nprintln(x - 3, \" \", x - 0.5, \" \", -9223372036854775807 - x);\nprintln(\"a\" - 1);"
+                        .to_string()
+                ),
+                InterpretResult::RuntimeError
+            );
+            assert_eq!(vm.stdout.concat(), "7 9.5 -9223372036854775817\n");
+            assert_eq!(
+                vm.stderr,
+                vec!["Runtime error: Operands of - must be numbers, got string and int at 3:13\n    [3:13] in script"]
+            );
+        }
+    }
+
+    #[test]
+    fn assign_global_defined_later() {
+        let mut vm = VM::new();
+        vm.set_plain_diagnostics(true);
+        assert_eq!(
+            vm.interpret("fun f() { g = 1; } let g = 0; f(); println(g);".to_string()),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.interpret("g = g + 1; println(g);".to_string()),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout.concat(), "1\n2\n");
+
+        assert_eq!(
+            vm.interpret("missing = 1;".to_string()),
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            vm.stderr,
+            vec!["Runtime error: Undefined variable missing at 1:1\n    [1:1] in script"]
+        );
+    }
+
+    #[test]
+    fn operand_overflow() {
+        let elements = vec!["0"; 70_000].join(", ");
+        let entries = vec!["0: 0"; 70_000].join(", ");
+        for (source, message) in [
+            (
+                format!("println(len([{}]));", elements),
+                "Too many elements in list literal",
+            ),
+            (
+                format!("println(len({{{}}}));", entries),
+                "Too many entries in map literal",
+            ),
+            (format!("println({});", elements), "Too many arguments"),
+        ] {
+            let mut vm = VM::new();
+            vm.set_plain_diagnostics(true);
+            assert_eq!(vm.interpret(source), InterpretResult::CompileError);
+            assert_eq!(vm.stderr.len(), 1);
+            assert!(vm.stderr[0].starts_with(&format!("Compile error: {} at 1:", message)));
+        }
+
+        let mut vm = VM::new();
+        let elements = vec!["0"; 65_535].join(", ");
+        assert_eq!(
+            vm.interpret(format!("println(len([{}]));", elements)),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stdout.concat(), "65535\n");
+    }
+
+    #[test]
+    fn many_distinct_constants() {
+        let entries = (0..50_000)
+            .map(|i| format!("{}: \"{}\"", i, i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let source = format!("let m = {{{}}}; println(len(m), \" \", m[49999]);", entries);
+
+        let start = std::time::Instant::now();
+        let mut vm = VM::new();
+        assert_eq!(vm.interpret(source), InterpretResult::Ok);
+        assert_eq!(vm.stdout.concat(), "50000 49999\n");
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn map_delete_keeps_order() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret(
+                r#"
+                    let m = {"a": 1, "b": 2, "c": 3, "d": 4};
+                    delete(m, "b");
+                    delete(m, "a");
+                    delete(m, "d");
+                    m["e"] = 5;
+                    m["c"] = 30;
+                    println(m, " ", len(m), " ", keys(m), " ", m["c"]);
+
+                    let big = {};
+                    let i = 0;
+                    while i < 100000 { big[i] = i; i = i + 1; }
+                    i = 0;
+                    while i < 99990 { delete(big, i); i = i + 1; }
+                    big["x"] = nil;
+                    println(len(big), " ", keys(big)[0], " ", big[99999], " ", values(big)[10]);
+                "#
+                .to_string()
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stdout.concat(),
+            "{\"c\": 30, \"e\": 5} 2 [\"c\", \"e\"] 30\n11 99990 99999 nil\n"
+        );
+    }
 }
@@ -1,15 +1,51 @@
+use std::fmt::Display;
+
 use crate::value::Value;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Span {
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+impl Span {
+    pub(crate) fn new(start: (usize, usize), end: (usize, usize)) -> Span {
+        Span { start, end }
+    }
+
+    pub(crate) fn line(&self) -> usize {
+        self.start.0
+    }
+
+    pub(crate) fn column(&self) -> usize {
+        self.start.1
+    }
+
+    pub(crate) fn end(&self) -> (usize, usize) {
+        self.end
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line(), self.column())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Token {
     kind: Kind,
-    // start: (usize, usize),
+    span: Span,
     value: Option<Value>,
 }
 
 impl Token {
-    pub(crate) fn new(kind: Kind, _start: (usize, usize), value: Option<Value>) -> Token {
-        Token { kind, value }
+    pub(crate) fn new(kind: Kind, span: Span, value: Option<Value>) -> Token {
+        Token { kind, span, value }
+    }
+
+    pub(crate) fn span(&self) -> Span {
+        self.span
     }
 
     pub(crate) fn kind(&self) -> Kind {
@@ -21,11 +57,23 @@ impl Token {
     }
 }
 
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.kind, &self.value) {
+            (Kind::Identifier, Some(value)) => write!(f, "identifier {}", value),
+            (Kind::Number, Some(value)) => write!(f, "number {}", value),
+            (Kind::String, Some(value)) => write!(f, "string {:?}", String::from(value.clone())),
+            (kind, _) => write!(f, "{}", kind),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub(crate) enum Kind {
     // Single-character tokens.
     Dot,
     Plus,
+    Colon,
     Star,
     Minus,
     Comma,
@@ -36,12 +84,15 @@ pub(crate) enum Kind {
     LeftParen,
     RightParen,
     RightBrace,
+    LeftBracket,
+    RightBracket,
 
     // One or two character tokens.
     Less,
     Equal,
     Concat,
     Greater,
+    SlashSlash,
     BangEqual,
     LessEqual,
     EqualEqual,
@@ -61,6 +112,7 @@ pub(crate) enum Kind {
     Let,
     Nil,
     Not,
+    Pub,
     Else,
     This,
     True,
@@ -75,6 +127,61 @@ pub(crate) enum Kind {
     Eof,
 }
 
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lexeme = match self {
+            Self::Dot => ".",
+            Self::Plus => "+",
+            Self::Colon => ":",
+            Self::Star => "*",
+            Self::Minus => "-",
+            Self::Comma => ",",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::Semicolon => ";",
+            Self::LeftBrace => "{",
+            Self::LeftParen => "(",
+            Self::RightParen => ")",
+            Self::RightBrace => "}",
+            Self::LeftBracket => "[",
+            Self::RightBracket => "]",
+            Self::Less => "<",
+            Self::Equal => "=",
+            Self::Concat => "<>",
+            Self::Greater => ">",
+            Self::SlashSlash => "//",
+            Self::BangEqual => "!=",
+            Self::LessEqual => "<=",
+            Self::EqualEqual => "==",
+            Self::GreaterEqual => ">=",
+            Self::Number => return write!(f, "number"),
+            Self::String => return write!(f, "string"),
+            Self::Identifier => return write!(f, "identifier"),
+            Self::If => "if",
+            Self::Or => "or",
+            Self::And => "and",
+            Self::For => "for",
+            Self::Fun => "fun",
+            Self::Let => "let",
+            Self::Nil => "nil",
+            Self::Not => "not",
+            Self::Pub => "pub",
+            Self::Else => "else",
+            Self::This => "this",
+            Self::True => "true",
+            Self::Class => "class",
+            Self::False => "false",
+            Self::Super => "super",
+            Self::While => "while",
+            Self::Return => "return",
+            Self::Expands => "expands",
+            Self::Error => return write!(f, "invalid token"),
+            Self::Eof => return write!(f, "end of script"),
+        };
+        write!(f, "`{}`", lexeme)
+    }
+}
+
 impl Kind {
     pub(crate) fn keyword_equivalent(candidate: &str) -> Option<Kind> {
         match candidate {
@@ -86,6 +193,7 @@ impl Kind {
             "let" => Some(Self::Let),
             "nil" => Some(Self::Nil),
             "not" => Some(Self::Not),
+            "pub" => Some(Self::Pub),
             "else" => Some(Self::Else),
             "this" => Some(Self::This),
             "true" => Some(Self::True),
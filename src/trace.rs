@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::disassembler::describe;
+use crate::function::Function;
+use crate::op::OpCode;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum TraceLevel {
+    Ops,
+    Stack,
+    Frame,
+}
+
+impl TraceLevel {
+    pub(crate) fn parse(level: &str) -> Option<TraceLevel> {
+        match level {
+            "ops" => Some(TraceLevel::Ops),
+            "stack" => Some(TraceLevel::Stack),
+            "frame" => Some(TraceLevel::Frame),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct Tracer {
+    level: TraceLevel,
+    function: Option<String>,
+    output: BufWriter<Box<dyn Write>>,
+}
+
+impl Tracer {
+    pub(crate) fn new(level: TraceLevel) -> Tracer {
+        Tracer {
+            level,
+            function: None,
+            output: BufWriter::new(Box::new(io::stderr())),
+        }
+    }
+
+    pub(crate) fn write_to(mut self, path: &str) -> io::Result<Tracer> {
+        self.output = BufWriter::new(Box::new(File::create(path)?));
+        Ok(self)
+    }
+
+    pub(crate) fn only(mut self, function: &str) -> Tracer {
+        self.function = Some(function.to_string());
+        self
+    }
+
+    pub(crate) fn flush(&mut self) {
+        let _ = self.output.flush();
+    }
+
+    pub(crate) fn trace(
+        &mut self,
+        function: &Function,
+        offset: usize,
+        op: OpCode,
+        operands: &[usize],
+        frame: &[Value],
+        depth: usize,
+    ) {
+        let name = match function.name().as_str() {
+            "##MAIN##" => "script".to_string(),
+            "##FIELDS##" => "fields".to_string(),
+            name => name.to_string(),
+        };
+        if self.function.as_ref().is_some_and(|only| *only != name) {
+            return;
+        }
+
+        let mut line = format!("{:>3} {:<12} {:0>4}  {:?}", depth, name, offset, op);
+        for operand in operands {
+            line += &format!(" {}", operand);
+        }
+
+        match self.level {
+            TraceLevel::Ops => {}
+            TraceLevel::Stack => {
+                let top = frame.last().map(describe).unwrap_or("-".to_string());
+                line = format!("{:<40}| {}", line, top);
+            }
+            TraceLevel::Frame => {
+                let slots: Vec<String> = frame.iter().map(describe).collect();
+                line = format!("{:<40}| [{}]", line, slots.join(", "));
+            }
+        }
+
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
@@ -1,34 +1,141 @@
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
-use crate::function::Function;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::class::{BoundMethod, Class, Instance};
+use crate::closure::Closure;
+use crate::map::Map;
+use crate::nif::Nif;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Value {
     Nil,
+    Int(i64),
+    BigInt(Rc<BigInt>),
     Number(f64),
     Boolean(bool),
     String(String),
-    Function((usize, Option<Function>)),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    Function(Rc<Closure>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(BoundMethod),
+    Nif(Rc<dyn Nif>),
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
 enum Type {
     Nil,
+    Boolean,
     Number,
     String,
-    Boolean,
+    List,
+    Map,
     Function,
+    Class,
+    Instance,
 }
 
 impl Value {
     fn get_type(&self) -> Type {
         match self {
             Self::Nil => Type::Nil,
-            Self::Number(_) => Type::Number,
+            Self::Int(_) | Self::BigInt(_) | Self::Number(_) => Type::Number,
             Self::String(_) => Type::String,
+            Self::List(_) => Type::List,
+            Self::Map(_) => Type::Map,
             Self::Boolean(_) => Type::Boolean,
             Self::Function(_) => Type::Function,
+            Self::Class(_) => Type::Class,
+            Self::Instance(_) => Type::Instance,
+            Self::BoundMethod(_) => Type::Function,
+            Self::Nif(_) => Type::Function,
+        }
+    }
+}
+
+impl Value {
+    pub(crate) fn is_hashable(&self) -> bool {
+        matches!(
+            self,
+            Self::Nil
+                | Self::Int(_)
+                | Self::BigInt(_)
+                | Self::Number(_)
+                | Self::String(_)
+                | Self::Boolean(_)
+        )
+    }
+
+    pub(crate) fn is_integer(&self) -> bool {
+        matches!(self, Self::Int(_) | Self::BigInt(_))
+    }
+
+    pub(crate) fn is_nan(&self) -> bool {
+        matches!(self, Self::Number(value) if value.is_nan())
+    }
+
+    pub(crate) fn repr(&self) -> String {
+        self.render(&mut vec![], true)
+    }
+
+    // lists and maps already being rendered are shown as [...] and {...}
+    fn render(&self, seen: &mut Vec<*const ()>, quoted: bool) -> String {
+        let address = match self {
+            Self::List(list) => Rc::as_ptr(list) as *const (),
+            Self::Map(map) => Rc::as_ptr(map) as *const (),
+            Self::String(value) if quoted => return format!("{:?}", value),
+            value => return String::from(value.clone()),
+        };
+        if seen.contains(&address) {
+            return match self {
+                Self::List(_) => "[...]".to_string(),
+                _ => "{...}".to_string(),
+            };
+        }
+
+        seen.push(address);
+        let rendered = match self {
+            Self::List(list) => {
+                let elements: Vec<String> = list
+                    .borrow()
+                    .iter()
+                    .map(|element| element.render(seen, true))
+                    .collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Self::Map(map) => {
+                let entries: Vec<String> = map
+                    .borrow()
+                    .entries()
+                    .map(|(key, value)| format!("{}: {}", key.repr(), value.render(seen, true)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            _ => unreachable!(),
+        };
+        seen.pop();
+        rendered
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Int(_) | Self::BigInt(_) => "int",
+            Self::Number(_) => "float",
+            Self::Boolean(_) => "boolean",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+            Self::Function(_) | Self::BoundMethod(_) | Self::Nif(_) => "function",
         }
     }
 }
@@ -42,13 +149,6 @@ impl From<Value> for f64 {
     }
 }
 
-impl From<Value> for i128 {
-    fn from(value: Value) -> Self {
-        let value: f64 = value.into();
-        value as i128
-    }
-}
-
 impl From<Value> for String {
     fn from(val: Value) -> Self {
         match val {
@@ -56,11 +156,15 @@ impl From<Value> for String {
             Value::String(value) => value,
             Value::Boolean(true) => "true".to_string(),
             Value::Boolean(false) => "false".to_string(),
-            Value::Number(value) => value.to_string(),
-            Value::Function((_, value)) => match value {
-                Some(function) => function.to_string(),
-                None => String::new(),
-            },
+            Value::Int(value) => value.to_string(),
+            Value::BigInt(value) => value.to_string(),
+            Value::Number(value) => format!("{:?}", value),
+            Value::List(_) | Value::Map(_) => val.render(&mut vec![], false),
+            Value::Function(closure) => closure.to_string(),
+            Value::Class(class) => class.to_string(),
+            Value::Instance(instance) => instance.borrow().to_string(),
+            Value::BoundMethod(bound_method) => bound_method.to_string(),
+            Value::Nif(nif) => nif.name(),
         }
     }
 }
@@ -74,6 +178,15 @@ impl From<Value> for bool {
     }
 }
 
+impl From<BigInt> for Value {
+    fn from(value: BigInt) -> Self {
+        match i64::try_from(&value) {
+            Ok(value) => Self::Int(value),
+            Err(_) => Self::BigInt(Rc::new(value)),
+        }
+    }
+}
+
 impl From<&str> for Value {
     fn from(value: &str) -> Self {
         Self::String(value.to_string())
@@ -89,42 +202,176 @@ impl Display for Value {
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match self.get_type() == other.get_type() {
-            false => false,
-            true => self.clone().to_string() == other.clone().to_string(),
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Int(v1), Self::Int(v2)) => v1 == v2,
+            (Self::Number(v1), Self::Number(v2)) => v1 == v2,
+            (Self::Int(v1), Self::Number(v2)) | (Self::Number(v2), Self::Int(v1)) => {
+                compare_int_float(*v1, *v2) == Some(Ordering::Equal)
+            }
+            (Self::BigInt(v1), Self::BigInt(v2)) => v1 == v2,
+            (Self::BigInt(v1), Self::Number(v2)) | (Self::Number(v2), Self::BigInt(v1)) => {
+                compare_big_float(v1, *v2) == Some(Ordering::Equal)
+            }
+            (Self::String(v1), Self::String(v2)) => v1 == v2,
+            (Self::Boolean(v1), Self::Boolean(v2)) => v1 == v2,
+            (Self::List(v1), Self::List(v2)) => Rc::ptr_eq(v1, v2),
+            (Self::Map(v1), Self::Map(v2)) => Rc::ptr_eq(v1, v2),
+            (Self::Function(v1), Self::Function(v2)) => Rc::ptr_eq(v1, v2),
+            (Self::Class(v1), Self::Class(v2)) => Rc::ptr_eq(v1, v2),
+            (Self::Instance(v1), Self::Instance(v2)) => Rc::ptr_eq(v1, v2),
+            (Self::BoundMethod(v1), Self::BoundMethod(v2)) => v1.is(v2),
+            (Self::Nif(v1), Self::Nif(v2)) => std::ptr::addr_eq(Rc::as_ptr(v1), Rc::as_ptr(v2)),
+            _ => false,
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Int(value) => (0, *value).hash(state),
+            Self::Number(value)
+                if compare_int_float(*value as i64, *value) == Some(Ordering::Equal) =>
+            {
+                (0, *value as i64).hash(state)
+            }
+            Self::Number(value) if value.is_nan() => (1, f64::NAN.to_bits()).hash(state),
+            Self::Number(value) => (1, value.to_bits()).hash(state),
+            Self::BigInt(value) => match value.to_f64() {
+                Some(float) if compare_big_float(value, float) == Some(Ordering::Equal) => {
+                    (1, float.to_bits()).hash(state)
+                }
+                _ => (2, value).hash(state),
+            },
+            Self::String(value) => value.hash(state),
+            Self::Boolean(value) => value.hash(state),
+            value => std::mem::discriminant(value).hash(state),
         }
     }
 }
 
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let self_type = self.get_type();
-        let other_type = other.get_type();
-        match self == other {
-            true => Some(Ordering::Equal),
-            false => match self_type == other_type {
-                true => match self_type {
-                    Type::Nil => Some(Ordering::Equal),
-                    _ => match (self, other) {
-                        (Self::Function(_), Self::Function(_)) => None,
-                        (Self::String(v1), Self::String(v2)) => v1.partial_cmp(v2),
-                        (Self::Number(v1), Self::Number(v2)) => v1.partial_cmp(v2),
-                        (Self::Boolean(v1), Self::Boolean(v2)) => v1.partial_cmp(v2),
-                        _ => None,
-                    },
-                },
-                false => match (self_type, other_type) {
-                    (Type::Nil, _) => Some(Ordering::Less),
-                    (_, Type::Nil) => Some(Ordering::Greater),
-                    (_, Type::Function) => Some(Ordering::Less),
-                    (Type::Function, _) => Some(Ordering::Greater),
-                    (_, Type::String) => Some(Ordering::Less),
-                    (Type::String, _) => Some(Ordering::Greater),
-                    (_, Type::Number) => Some(Ordering::Less),
-                    (Type::Number, _) => Some(Ordering::Greater),
-                    _ => None,
-                },
+        match (self, other) {
+            (Self::Int(v1), Self::Int(v2)) => v1.partial_cmp(v2),
+            (Self::Number(v1), Self::Number(v2)) => v1.partial_cmp(v2),
+            (Self::Int(v1), Self::Number(v2)) => compare_int_float(*v1, *v2),
+            (Self::Number(v1), Self::Int(v2)) => compare_int_float(*v2, *v1).map(Ordering::reverse),
+            (Self::BigInt(v1), Self::BigInt(v2)) => v1.partial_cmp(v2),
+            (Self::BigInt(v1), Self::Int(v2)) => v1.as_ref().partial_cmp(&BigInt::from(*v2)),
+            (Self::Int(v1), Self::BigInt(v2)) => BigInt::from(*v1).partial_cmp(v2),
+            (Self::BigInt(v1), Self::Number(v2)) => compare_big_float(v1, *v2),
+            (Self::Number(v1), Self::BigInt(v2)) => {
+                compare_big_float(v2, *v1).map(Ordering::reverse)
+            }
+            (Self::String(v1), Self::String(v2)) => v1.partial_cmp(v2),
+            (Self::Boolean(v1), Self::Boolean(v2)) => v1.partial_cmp(v2),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    pub(crate) fn is_comparable(&self, other: &Value) -> bool {
+        matches!(
+            (self, other),
+            (
+                Self::Int(_) | Self::BigInt(_) | Self::Number(_),
+                Self::Int(_) | Self::BigInt(_) | Self::Number(_)
+            ) | (Self::String(_), Self::String(_))
+                | (Self::Boolean(_), Self::Boolean(_))
+        )
+    }
+
+    pub(crate) fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (
+                Self::Int(_) | Self::BigInt(_) | Self::Number(_),
+                Self::Int(_) | Self::BigInt(_) | Self::Number(_),
+            ) => Some(
+                self.partial_cmp(other)
+                    .unwrap_or(self.is_nan().cmp(&other.is_nan())),
+            ),
+            (Self::List(v1), Self::List(v2)) if !Rc::ptr_eq(v1, v2) => {
+                let (v1, v2) = (v1.borrow(), v2.borrow());
+                for (left, right) in v1.iter().zip(v2.iter()) {
+                    match left.compare(right)? {
+                        Ordering::Equal => continue,
+                        ordering => return Some(ordering),
+                    }
+                }
+                Some(v1.len().cmp(&v2.len()))
+            }
+            _ if self == other => Some(Ordering::Equal),
+            _ if self.is_comparable(other) => self.partial_cmp(other),
+            _ => match self.get_type().cmp(&other.get_type()) {
+                Ordering::Equal => None,
+                ordering => Some(ordering),
             },
         }
     }
 }
+
+fn compare_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    if float >= i64::MAX as f64 {
+        return Some(Ordering::Less);
+    }
+    if float < i64::MIN as f64 {
+        return Some(Ordering::Greater);
+    }
+
+    let whole = float.trunc();
+    match int.cmp(&(whole as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(float - whole)),
+        ordering => Some(ordering),
+    }
+}
+
+fn compare_big_float(big: &BigInt, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    if float.is_infinite() {
+        return Some(0f64.total_cmp(&float));
+    }
+
+    let whole = float.trunc();
+    match big.cmp(&BigInt::from_f64(whole).unwrap()) {
+        Ordering::Equal => 0.0.partial_cmp(&(float - whole)),
+        ordering => Some(ordering),
+    }
+}
+
+pub(crate) fn list_index(index: &Value, length: usize) -> Result<usize, String> {
+    let value = match index {
+        Value::Int(value) => value,
+        Value::BigInt(value) => {
+            return Err(format!(
+                "List index {} is out of range for a list of length {}",
+                value, length
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "List index must be an integer, got {}",
+                index.repr()
+            ))
+        }
+    };
+
+    let position = match *value < 0 {
+        true => value.checked_add_unsigned(length as u64),
+        false => Some(*value),
+    };
+    match position {
+        Some(position) if position >= 0 && (position as usize) < length => Ok(position as usize),
+        _ => Err(format!(
+            "List index {} is out of range for a list of length {}",
+            value, length
+        )),
+    }
+}
@@ -1,65 +1,160 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::env::var_os;
+use std::rc::Rc;
 use std::time::Instant;
 
-use crate::chunk::Chunk;
+use crate::arithmetic;
+use crate::class::{BoundMethod, Class, Instance};
+use crate::closure::{Closure, Upvalue};
 use crate::compiler::Compiler;
-use crate::error::InterpretResult;
+use crate::error::{ErrorContext, InterpretResult, LoxError};
 use crate::function::Function;
-use crate::nif::resolve_nif;
+use crate::map::Map;
+use crate::nif::nifs;
 use crate::op::OpCode;
-use crate::value::Value;
+use crate::trace::Tracer;
+use crate::value::{list_index, Value};
+
+const FRAMES_MAX: usize = 1 << 20;
+const TRACE_EDGE: usize = 10;
+
+enum FrameKind {
+    Function,
+    Initializer,
+}
+
+struct CallFrame {
+    ip: usize,
+    kind: FrameKind,
+    slot_base: usize,
+    closure: Rc<Closure>,
+    function: Rc<Function>,
+}
+
+impl CallFrame {
+    fn new(closure: Rc<Closure>, slot_base: usize, kind: FrameKind) -> CallFrame {
+        CallFrame {
+            ip: 0,
+            kind,
+            slot_base,
+            function: closure.function(),
+            closure,
+        }
+    }
+}
 
 pub(crate) struct VM {
     #[cfg(test)]
     pub stdout: Vec<String>,
+    #[cfg(test)]
+    pub stderr: Vec<String>,
 
+    file: String,
+    source: String,
+    plain_diagnostics: bool,
+    optimize: bool,
+    tracer: Option<Tracer>,
     start_time: Instant,
-    stack: Vec<Vec<Value>>,
-    constants: Chunk<Value>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
     globals: HashMap<String, Value>,
-    functions: Vec<(Function, u128)>,
-    loops: HashMap<String, Function>,
 }
 
 impl VM {
     pub(crate) fn new() -> VM {
+        let globals = nifs()
+            .into_iter()
+            .map(|nif| (nif.name(), Value::Nif(nif)))
+            .collect();
+
         VM {
             #[cfg(test)]
             stdout: vec![],
-
-            functions: vec![],
-            stack: vec![vec![]],
-            loops: HashMap::new(),
-            constants: Chunk::new(),
-            globals: HashMap::new(),
+            #[cfg(test)]
+            stderr: vec![],
+
+            file: "<script>".to_string(),
+            source: String::new(),
+            plain_diagnostics: false,
+            optimize: true,
+            tracer: None,
+            stack: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            globals,
             start_time: Instant::now(),
         }
     }
 
     pub(crate) fn interpret(&mut self, source: String) -> InterpretResult {
-        let main_function = Function::new_main("##MAIN##".to_string());
-        let mut compiler = Compiler::new(self, main_function, &source);
-        match compiler.compile() {
+        match self.compile(source) {
             Ok(main_function) => self.run(main_function),
-            _ => InterpretResult::CompileError,
+            Err(result) => result,
         }
     }
 
-    pub(crate) fn run(&mut self, function: Function) -> InterpretResult {
-        let debug = var_os("DEBUG").is_some();
+    pub(crate) fn compile(&mut self, source: String) -> Result<Function, InterpretResult> {
+        self.source = source.clone();
+        let main_function = Function::new_main("##MAIN##".to_string());
+        Compiler::new(self, main_function, &source).compile()
+    }
 
-        let mut iterator = function.into_iter().peekable();
-        while let Some(current) = iterator.next() {
-            let op_code = OpCode::from(current as u8);
+    pub(crate) fn run(&mut self, function: Function) -> InterpretResult {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        let closure = Rc::new(Closure::new(Rc::new(function), vec![]));
+        self.frames
+            .push(CallFrame::new(closure, 0, FrameKind::Function));
+
+        let result = self.execute();
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.flush();
+        }
+        if !matches!(result, InterpretResult::Ok) {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
 
-            if debug {
-                println!("\n{} OpCode\n{:?}", function, op_code);
-                println!("\n{}", self.stack.len());
-                if self.stack.len() > 1 {
-                    println!("{:#?}", self.stack.get(self.stack.len() - 2));
+    fn execute(&mut self) -> InterpretResult {
+        loop {
+            let offset = self.frame().ip;
+            let Some(current) = self.read_byte() else {
+                if self.frames.len() > 1 {
+                    return self.runtime_error("Function ended without returning");
                 }
-                println!("{:#?}", self.stack.last());
+                self.frames.pop();
+                return InterpretResult::Ok;
+            };
+            let mut op_code = OpCode::from(current);
+
+            let wide = op_code == OpCode::Wide;
+            if wide {
+                let Some(current) = self.read_byte() else {
+                    return self.runtime_error("Unexpected end of bytecode");
+                };
+                op_code = OpCode::from(current);
+            }
+            let Some(operands) = self.read_operands(op_code, wide) else {
+                return self.runtime_error("Unexpected end of bytecode");
+            };
+
+            if let Some(tracer) = self.tracer.as_mut() {
+                let frame = self.frames.last().unwrap();
+                let operands = &operands[..op_code.operands().len()];
+                let slots = self.stack.get(frame.slot_base..).unwrap_or_default();
+                tracer.trace(
+                    &frame.function,
+                    offset,
+                    op_code,
+                    operands,
+                    slots,
+                    self.frames.len(),
+                );
             }
 
             match op_code {
@@ -69,77 +164,53 @@ impl VM {
                         None => Value::Nil,
                     };
 
-                    if let Value::Function((address, _)) = return_value {
-                        if let Some(returned_function) = self.functions.get_mut(address).cloned() {
-                            self.functions.remove(address);
-                            self.functions
-                                .insert(address, (returned_function.0, returned_function.1 - 1));
-                        };
+                    let Some(frame) = self.frames.pop() else {
+                        return self.runtime_error("Return outside of a call frame");
                     };
-
-                    if !function.is_loop() {
-                        self.stack.pop();
+                    self.close_upvalues(frame.slot_base);
+                    if self.frames.is_empty() {
+                        return InterpretResult::Ok;
                     }
-                    self.stack_push(return_value);
 
-                    return InterpretResult::Ok;
+                    self.stack.truncate(frame.slot_base);
+                    if let FrameKind::Function = frame.kind {
+                        self.stack_push(return_value);
+                    }
                 }
 
                 OpCode::Constant => {
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
+                    let address = operands[0];
                     let Some(constant) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Invalid constant operand");
                     };
 
-                    match constant {
-                        Value::Function((address, None)) => {
-                            if let Some((function, _)) = self.functions.get(*address) {
-                                self.stack_push(Value::Function((*address, Some(function.clone()))))
-                            } else {
-                                return InterpretResult::RuntimeError;
-                            }
-                        }
-                        _ => self.stack_push(constant.clone()),
-                    }
+                    self.stack_push(constant.clone());
                 }
 
                 OpCode::Negate => {
-                    let Some(Value::Number(value)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                    let Some(value) = self.stack_pop() else {
+                        return self.runtime_error("Stack underflow");
                     };
-                    self.stack_push(Value::Number(-value));
+                    match arithmetic::negate(&value) {
+                        Ok(value) => self.stack_push(value),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 }
 
                 OpCode::Not => {
                     let Some(value) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    match value {
-                        Value::Nil => self.stack_push(Value::Boolean(true)),
-                        Value::Boolean(value) => self.stack_push(Value::Boolean(!value)),
-
-                        Value::Number(value) if value == 0.0 => {
-                            self.stack_push(Value::Boolean(true))
-                        }
-                        Value::Number(_) => self.stack_push(Value::Boolean(false)),
-
-                        Value::String(value) if value.is_empty() => {
-                            self.stack_push(Value::Boolean(true))
-                        }
-                        Value::String(_) => self.stack_push(Value::Boolean(false)),
-                        _ => return InterpretResult::RuntimeError,
-                    }
+                    self.stack_push(Value::Boolean(self.is_falsey(&value)));
                 }
 
                 OpCode::Concat => {
                     let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
                     let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
 
                     let right: String = right.into();
@@ -148,105 +219,93 @@ impl VM {
                     self.stack_push(Value::String(left + &right))
                 }
 
-                OpCode::Add => {
-                    let Some(Value::Number(right)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(Value::Number(left)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::Add
+                | OpCode::Rem
+                | OpCode::Divide
+                | OpCode::Multiply
+                | OpCode::Subtract
+                | OpCode::FloorDivide => {
+                    let (Some(right), Some(left)) = (self.stack_pop(), self.stack_pop()) else {
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    self.stack_push(Value::Number(left + right))
-                }
-
-                OpCode::Multiply => {
-                    let Some(Value::Number(right)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                    let operation = match op_code {
+                        OpCode::Add => arithmetic::add,
+                        OpCode::Rem => arithmetic::remainder,
+                        OpCode::Divide => arithmetic::divide,
+                        OpCode::Multiply => arithmetic::multiply,
+                        OpCode::Subtract => arithmetic::subtract,
+                        _ => arithmetic::floor_divide,
                     };
-                    let Some(Value::Number(left)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    self.stack_push(Value::Number(left * right))
+                    match operation(&left, &right) {
+                        Ok(value) => self.stack_push(value),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 }
 
-                OpCode::Rem => {
-                    let Some(Value::Number(right)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::AddConstant => {
+                    let address = operands[0];
+                    let Some(right) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
                     };
-                    let Some(Value::Number(left)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                    let Some(left) = self.stack_pop() else {
+                        return self.runtime_error("Stack underflow");
                     };
-                    self.stack_push(Value::Number(left % right))
-                }
 
-                OpCode::Divide => {
-                    let Some(Value::Number(right)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(Value::Number(left)) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    self.stack_push(Value::Number(left / right))
+                    match arithmetic::add(&left, &right) {
+                        Ok(value) => self.stack_push(value),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 }
 
                 OpCode::Equal => {
                     let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
                     let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
                     self.stack_push(Value::Boolean(left == right));
                 }
 
                 OpCode::NotEqual => {
                     let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
                     let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
                     self.stack_push(Value::Boolean(left != right));
                 }
 
-                OpCode::GreaterEqual => {
-                    let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::Less | OpCode::Greater | OpCode::LessEqual | OpCode::GreaterEqual => {
+                    let (Some(right), Some(left)) = (self.stack_pop(), self.stack_pop()) else {
+                        return self.runtime_error("Stack underflow");
                     };
-                    self.stack_push(Value::Boolean(left >= right));
-                }
-
-                OpCode::Greater => {
-                    let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    self.stack_push(Value::Boolean(left > right));
-                }
 
-                OpCode::LessEqual => {
-                    let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                    let operator = match op_code {
+                        OpCode::Less => "<",
+                        OpCode::Greater => ">",
+                        OpCode::LessEqual => "<=",
+                        _ => ">=",
                     };
-                    let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    self.stack_push(Value::Boolean(left <= right));
-                }
+                    if !left.is_comparable(&right) {
+                        let message = format!(
+                            "Operands of {} must be two numbers, two strings or two booleans, got {} and {}",
+                            operator,
+                            left.type_name(),
+                            right.type_name()
+                        );
+                        return self.runtime_error(message.as_str());
+                    }
 
-                OpCode::Less => {
-                    let Some(right) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(left) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                    let result = match op_code {
+                        OpCode::Less => left < right,
+                        OpCode::Greater => left > right,
+                        OpCode::LessEqual => left <= right,
+                        _ => left >= right,
                     };
-                    self.stack_push(Value::Boolean(left < right));
+                    self.stack_push(Value::Boolean(result));
                 }
 
                 OpCode::Pop => {
@@ -258,311 +317,789 @@ impl VM {
                 }
 
                 OpCode::MakeClosure => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    let Some(Value::Number(address)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                    let address = operands[0];
+                    let Some(Value::Function(prototype)) = self.get_constant(address).cloned()
+                    else {
+                        return self.runtime_error("Invalid function operand");
                     };
 
-                    let address = *address;
-                    let Some((ref mut function, _)) = self.functions.get_mut(address as usize)
-                    else {
-                        return InterpretResult::RuntimeError;
-                    };
-
-                    function
-                        .captures()
-                        .iter()
-                        .for_each(|(name, (frame, address, _))| {
-                            function.populate_capture(
-                                name.clone(),
-                                self.stack
-                                    .get(*frame)
-                                    .unwrap()
-                                    .get(*address)
-                                    .cloned()
-                                    .unwrap(),
-                            );
-                        });
+                    let closure = match self.make_closure(prototype.function()) {
+                        Ok(closure) => closure,
+                        Err(result) => return result,
+                    };
+                    self.stack_push(Value::Function(closure));
                 }
 
                 OpCode::GetCaptured => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                    let address = operands[0];
+                    let Some(capture) = self.frame().closure.get_capture(address) else {
+                        return self.runtime_error("Invalid captured variable");
                     };
-                    let Some(Value::String(variable_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+
+                    let value = match &*capture.borrow() {
+                        Upvalue::Open(slot) => self.stack.get(*slot).cloned(),
+                        Upvalue::Closed(value) => Some(value.clone()),
+                    };
+                    let Some(value) = value else {
+                        return self.runtime_error("Invalid captured variable");
                     };
+                    self.stack_push(value);
+                }
 
-                    let Some(value) = function.get_capture(variable_name.clone()) else {
-                        return InterpretResult::RuntimeError;
+                OpCode::SetCaptured => {
+                    let address = operands[0];
+                    let Some(capture) = self.frame().closure.get_capture(address) else {
+                        return self.runtime_error("Invalid captured variable");
                     };
-                    self.stack_push(value.clone());
+                    let Some(value) = self.stack_peek() else {
+                        return self.runtime_error("Stack underflow");
+                    };
+
+                    let slot = match &mut *capture.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack.get_mut(*slot).map(|slot| *slot = value),
+                        Upvalue::Closed(closed) => {
+                            *closed = value;
+                            Some(())
+                        }
+                    };
+                    if slot.is_none() {
+                        return self.runtime_error("Invalid captured variable");
+                    }
                 }
 
-                OpCode::DefGlobal => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::CloseCaptured => {
+                    let Some(top) = self.stack.len().checked_sub(1) else {
+                        return self.runtime_error("Stack underflow");
                     };
+                    self.close_upvalues(top);
+                    self.stack_pop();
+                }
+
+                OpCode::DefGlobal => {
+                    let address = operands[0];
                     let Some(Value::String(variable_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Invalid constant operand");
                     };
                     let variable_name = variable_name.clone();
 
                     let Some(value) = self.stack_pop() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
 
                     self.globals.insert(variable_name, value.clone());
                 }
 
                 OpCode::SetGlobal => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
+                    let address = operands[0];
                     let Some(Value::String(variable_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Invalid constant operand");
                     };
                     let variable_name = variable_name.clone();
 
                     let Some(value) = self.stack_peek() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    if self.globals.insert(variable_name, value).is_none() {
-                        return InterpretResult::RuntimeError;
-                    };
+                    if !self.globals.contains_key(&variable_name) {
+                        return self.undefined_variable(&variable_name);
+                    }
+                    self.globals.insert(variable_name, value);
                 }
 
                 OpCode::GetGlobal => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
+                    let address = operands[0];
                     let Some(Value::String(variable_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Invalid constant operand");
                     };
                     let variable_name = variable_name.clone();
 
-                    let Some(value) = self.globals.get(&variable_name) else {
-                        return InterpretResult::RuntimeError;
+                    let Some(value) = self.globals.get(&variable_name).cloned() else {
+                        return self.undefined_variable(&variable_name);
                     };
-                    self.stack_push(value.clone());
+                    self.stack_push(value);
                 }
 
                 OpCode::GetLocal => {
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
+                    let address = operands[0];
                     let Some(value) = self.stack_get(address) else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Invalid local variable");
                     };
                     self.stack_push(value.clone());
                 }
 
                 OpCode::SetLocal => {
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
-                    };
-                    iterator.next();
+                    let address = operands[0];
                     let Some(value) = self.stack_peek() else {
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    self.stack_insert(address, value);
+                    if !self.stack_set(address, value) {
+                        return self.runtime_error("Invalid local variable");
+                    }
                 }
 
-                OpCode::JumpIfFalse => {
-                    let Some(value) = self.stack_peek() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::SetLocalPop => {
+                    let address = operands[0];
+                    let Some(value) = self.stack_pop() else {
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    let is_falsey = match self.is_falsey(&value) {
-                        Some(result) => result,
-                        None => return InterpretResult::RuntimeError,
-                    };
+                    if !self.stack_set(address, value) {
+                        return self.runtime_error("Invalid local variable");
+                    }
+                }
 
-                    let Some(size) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::JumpIfFalse | OpCode::JumpIfTrue => {
+                    let Some(value) = self.stack_peek() else {
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    if is_falsey {
-                        for _ in 0..size {
-                            iterator.next();
-                        }
+                    let is_falsey = self.is_falsey(&value);
+
+                    let size = operands[0];
+
+                    if is_falsey == (op_code == OpCode::JumpIfFalse) {
+                        self.frame_mut().ip += size;
                     }
                 }
 
                 OpCode::Jump => {
-                    let Some(size) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                    let size = operands[0];
+                    self.frame_mut().ip += size;
+                }
+
+                OpCode::Loop => {
+                    let distance = operands[0];
+                    self.frame_mut().ip -= distance;
+                }
+
+                OpCode::Class => {
+                    let address = operands[0];
+                    let has_superclass = operands[1];
+                    let Some(Value::Class(class)) = self.get_constant(address) else {
+                        return self.runtime_error("Invalid constant operand");
                     };
+                    let mut class = class.as_ref().clone();
+                    for closure in class.closures_mut() {
+                        *closure = match self.make_closure(closure.function()) {
+                            Ok(closure) => closure,
+                            Err(result) => return result,
+                        };
+                    }
 
-                    for _ in 0..size {
-                        iterator.next();
+                    if has_superclass == 1 {
+                        let superclass = match self.stack_pop() {
+                            Some(Value::Class(superclass)) => superclass,
+                            Some(value) => {
+                                let message = format!(
+                                    "{} can only expand a class, got {}",
+                                    class.name(),
+                                    value.type_name()
+                                );
+                                return self.runtime_error(message.as_str());
+                            }
+                            None => return self.runtime_error("Stack underflow"),
+                        };
+                        class.set_superclass(superclass);
                     }
+
+                    self.stack_push(Value::Class(Rc::new(class)));
                 }
 
-                OpCode::Loop => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::BuildList => {
+                    let count = operands[0];
+                    if self.stack.len() < count {
+                        return self.runtime_error("Stack underflow");
+                    }
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack_push(Value::List(Rc::new(RefCell::new(elements))));
+                }
+
+                OpCode::BuildMap => {
+                    let count = operands[0];
+                    if self.stack.len() < count * 2 {
+                        return self.runtime_error("Stack underflow");
+                    }
+                    let elements = self.stack.split_off(self.stack.len() - count * 2);
+
+                    let mut map = Map::new();
+                    for entry in elements.chunks(2) {
+                        if let Err(message) = Self::check_key(&entry[0]) {
+                            return self.runtime_error(&message);
+                        }
+                        map.insert(entry[0].clone(), entry[1].clone());
+                    }
+                    self.stack_push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+
+                OpCode::GetIndex => {
+                    let (Some(index), Some(object)) = (self.stack_pop(), self.stack_pop()) else {
+                        return self.runtime_error("Stack underflow");
                     };
-                    let Some(Value::String(loop_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+
+                    let value = match &object {
+                        Value::List(list) => list_index(&index, list.borrow().len())
+                            .map(|position| list.borrow()[position].clone()),
+                        Value::Map(map) => Self::check_key(&index).and_then(|_| {
+                            map.borrow()
+                                .get(&index)
+                                .cloned()
+                                .ok_or_else(|| format!("Key {} is not in the map", index.repr()))
+                        }),
+                        value => Err(Self::index_error(value)),
                     };
+                    match value {
+                        Ok(value) => self.stack_push(value),
+                        Err(message) => return self.runtime_error(&message),
+                    }
+                }
 
-                    let Some(lp) = self.get_loop(loop_name) else {
-                        return InterpretResult::RuntimeError;
+                OpCode::SetIndex => {
+                    let (Some(value), Some(index), Some(object)) =
+                        (self.stack_pop(), self.stack_pop(), self.stack_pop())
+                    else {
+                        return self.runtime_error("Stack underflow");
                     };
 
-                    self.stack.push(vec![]);
-                    let name = lp.name().clone();
-                    match self.run(lp) {
-                        InterpretResult::Ok => (),
-                        _ => return InterpretResult::RuntimeError,
+                    let result = match &object {
+                        Value::List(list) => {
+                            let length = list.borrow().len();
+                            list_index(&index, length)
+                                .map(|position| list.borrow_mut()[position] = value.clone())
+                        }
+                        Value::Map(map) => Self::check_key(&index)
+                            .map(|_| map.borrow_mut().insert(index, value.clone())),
+                        value => Err(Self::index_error(value)),
                     };
-                    self.remove_loop(&name);
+                    if let Err(message) = result {
+                        return self.runtime_error(&message);
+                    }
+                    self.stack_push(value);
                 }
 
-                OpCode::Call => {
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::GetProperty => {
+                    let address = operands[0];
+                    let via_this = operands[1];
+                    let Some(Value::String(name)) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
                     };
-                    let Some(Value::Number(scope)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                    let instance = match self.pop_instance(&name) {
+                        Ok(instance) => instance,
+                        Err(result) => return result,
                     };
-                    let scope = *scope as u128;
 
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                    let class = instance.borrow().class();
+                    let value = match class.field_visibility(&name) {
+                        Some(is_pub) if is_pub || via_this == 1 => instance.borrow().get(&name),
+                        Some(_) => None,
+                        None => match class.find_method(&name) {
+                            Some((method, is_pub)) if is_pub || via_this == 1 => {
+                                Some(Value::BoundMethod(BoundMethod::new(instance, method)))
+                            }
+                            _ => None,
+                        },
                     };
-                    let Some(Value::Number(args)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+
+                    let Some(value) = value else {
+                        return self.member_error(&class, &name);
                     };
-                    let args = *args as u128;
+                    self.stack_push(value);
+                }
 
-                    iterator.next();
-                    let Some(address) = iterator.next() else {
-                        return InterpretResult::RuntimeError;
+                OpCode::SetProperty => {
+                    let address = operands[0];
+                    let via_this = operands[1];
+                    let Some(Value::String(name)) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
                     };
-                    let Some(Value::String(function_name)) = self.get_constant(address) else {
-                        return InterpretResult::RuntimeError;
+                    let Some(value) = self.stack_pop() else {
+                        return self.runtime_error("Stack underflow");
+                    };
+                    let instance = match self.pop_instance(&name) {
+                        Ok(instance) => instance,
+                        Err(result) => return result,
                     };
-                    let function_name = function_name.clone();
 
-                    match resolve_nif(&function_name) {
-                        Some(nif) => {
-                            let arity = nif.arity();
+                    let class = instance.borrow().class();
+                    match class.field_visibility(&name) {
+                        Some(is_pub) if is_pub || via_this == 1 => {
+                            instance.borrow_mut().set(&name, value.clone());
+                        }
+                        None if class.find_method(&name).is_some() => {
+                            let message =
+                                format!("Cannot assign to method {} of {}", name, class.name());
+                            return self.runtime_error(message.as_str());
+                        }
+                        _ => return self.member_error(&class, &name),
+                    }
+                    self.stack_push(value);
+                }
 
-                            if arity.is_some() && arity.unwrap() != args {
-                                return InterpretResult::RuntimeError;
-                            }
+                OpCode::Invoke => {
+                    let address = operands[0];
+                    let args = operands[1];
+                    let via_this = operands[2];
+                    let Some(Value::String(name)) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
+                    };
 
-                            match nif.call(self, args as usize) {
-                                Ok(_) => (),
-                                _ => return InterpretResult::RuntimeError,
-                            }
+                    let instance = match self.stack_peek_at(args) {
+                        Some(Value::Instance(instance)) => instance,
+                        Some(value) => {
+                            let message = format!(
+                                "Cannot call {} on a {} value, only instances have methods",
+                                name,
+                                value.type_name()
+                            );
+                            return self.runtime_error(message.as_str());
                         }
+                        None => return self.runtime_error("Stack underflow"),
+                    };
 
-                        None => {
-                            let function = self.resolve_function(&function_name, scope);
-
-                            if function.is_none() {
-                                return InterpretResult::RuntimeError;
+                    let class = instance.borrow().class();
+                    let callee = instance.borrow().get(&name);
+                    let result = match (class.field_visibility(&name), callee) {
+                        (Some(is_pub), Some(callee)) if is_pub || via_this == 1 => {
+                            self.stack.remove(self.stack.len() - args - 1);
+                            self.call_value(callee, args)
+                        }
+                        (Some(_), _) => self.member_error(&class, &name),
+                        (None, _) => match class.find_method(&name) {
+                            Some((method, is_pub)) if is_pub || via_this == 1 => {
+                                self.call_method(method, args)
                             }
+                            _ => self.member_error(&class, &name),
+                        },
+                    };
 
-                            let (function, _) = function.unwrap();
+                    if result != InterpretResult::Ok {
+                        return result;
+                    }
+                }
 
-                            if function.arity() != args {
-                                return InterpretResult::RuntimeError;
-                            }
+                OpCode::GetSuper => {
+                    let address = operands[0];
+                    let Some(Value::String(name)) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
+                    };
+                    let Some(Value::Class(superclass)) = self.stack_pop() else {
+                        return self.runtime_error("Superclass must be a class");
+                    };
+                    let Some(Value::Instance(instance)) = self.stack_pop() else {
+                        return self.runtime_error("super must be used on an instance");
+                    };
 
-                            let mut substack = vec![];
-                            for _ in 0..args {
-                                substack.push(self.stack_pop().unwrap());
-                            }
-                            substack.reverse();
-                            self.stack.push(substack);
+                    let Some((method, _)) = superclass.find_method(&name) else {
+                        let message = format!("{} has no method {}", superclass.name(), name);
+                        return self.runtime_error(message.as_str());
+                    };
+                    self.stack_push(Value::BoundMethod(BoundMethod::new(instance, method)));
+                }
 
-                            match self.run(function.clone()) {
-                                InterpretResult::Ok => (),
-                                _ => return InterpretResult::RuntimeError,
-                            }
-                        }
+                OpCode::InvokeSuper => {
+                    let address = operands[0];
+                    let args = operands[1];
+                    let Some(Value::String(name)) = self.get_constant(address).cloned() else {
+                        return self.runtime_error("Invalid constant operand");
+                    };
+                    let Some(Value::Class(superclass)) = self.stack_pop() else {
+                        return self.runtime_error("Superclass must be a class");
+                    };
+
+                    let Some(Value::Instance(_)) = self.stack_peek_at(args) else {
+                        return self.runtime_error("super must be used on an instance");
+                    };
+
+                    let Some((method, _)) = superclass.find_method(&name) else {
+                        let message = format!("{} has no method {}", superclass.name(), name);
+                        return self.runtime_error(message.as_str());
+                    };
+
+                    let result = self.call_method(method, args);
+                    if result != InterpretResult::Ok {
+                        return result;
                     }
                 }
 
-                _ => return InterpretResult::CompileError,
+                OpCode::Call => {
+                    let args = operands[0];
+                    let Some(index) = self.stack.len().checked_sub(args + 1) else {
+                        return self.runtime_error("Stack underflow");
+                    };
+
+                    let callee = self.stack.remove(index);
+                    let result = self.call_value(callee, args);
+                    if result != InterpretResult::Ok {
+                        return result;
+                    }
+                }
+
+                op_code => {
+                    let message = format!("Unknown instruction {:?}", op_code);
+                    return self.runtime_error(message.as_str());
+                }
             }
         }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let frame = self.frames.last_mut()?;
+        let code = frame.function.code(frame.ip);
+        frame.ip += 1;
+        code
+    }
+
+    fn read_operands(&mut self, op_code: OpCode, wide: bool) -> Option<[usize; 3]> {
+        let mut operands = [0; 3];
+        for (index, operand) in op_code.operands().iter().enumerate() {
+            for _ in 0..operand.width(wide) {
+                operands[index] = (operands[index] << 8) | self.read_byte()? as usize;
+            }
+        }
+        Some(operands)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
 
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, slot_base: usize, kind: FrameKind) -> InterpretResult {
+        if self.frames.len() >= FRAMES_MAX {
+            return self.runtime_error("Stack overflow");
+        }
+
+        self.frames.push(CallFrame::new(closure, slot_base, kind));
         InterpretResult::Ok
     }
 
-    pub(crate) fn add_constant(&mut self, constant: Value) -> usize {
-        self.constants.add(constant)
+    fn call_value(&mut self, callee: Value, args: usize) -> InterpretResult {
+        match callee {
+            Value::Function(closure) => {
+                let function = closure.function();
+                if function.arity() != args as u128 {
+                    return self.arity_error(function.name(), function.arity(), args);
+                }
+
+                let slot_base = self.stack.len() - args;
+                self.call(closure, slot_base, FrameKind::Function)
+            }
+            Value::Nif(nif) => match nif.arity() {
+                Some(arity) if arity != args as u128 => self.arity_error(nif.name(), arity, args),
+                _ => match nif.call(self, args) {
+                    Ok(_) => InterpretResult::Ok,
+                    Err(error) => self.raise(error),
+                },
+            },
+            Value::Class(class) => self.instantiate(class, args),
+            Value::BoundMethod(bound_method) => {
+                let receiver = Value::Instance(bound_method.receiver());
+                self.stack.insert(self.stack.len() - args, receiver);
+                self.call_method(bound_method.method(), args)
+            }
+            value => {
+                let message = format!(
+                    "Can only call functions and classes, got {}",
+                    value.type_name()
+                );
+                self.runtime_error(message.as_str())
+            }
+        }
+    }
+
+    fn call_method(&mut self, method: Rc<Closure>, args: usize) -> InterpretResult {
+        let function = method.function();
+        if function.arity() != args as u128 {
+            return self.arity_error(function.name(), function.arity(), args);
+        }
+
+        let slot_base = self.stack.len() - args - 1;
+        self.call(method, slot_base, FrameKind::Function)
     }
 
-    pub(crate) fn add_function(&mut self, scope_depth: u128, function: Function) -> usize {
-        self.functions.push((function, scope_depth));
-        self.functions.len() - 1
+    fn pop_instance(&mut self, name: &String) -> Result<Rc<RefCell<Instance>>, InterpretResult> {
+        match self.stack_pop() {
+            Some(Value::Instance(instance)) => Ok(instance),
+            Some(value) => {
+                let message = format!(
+                    "Cannot access {} on a {} value, only instances have properties",
+                    name,
+                    value.type_name()
+                );
+                Err(self.runtime_error(message.as_str()))
+            }
+            None => Err(self.runtime_error("Stack underflow")),
+        }
     }
 
-    pub(crate) fn add_loop(&mut self, lp: Function) {
-        self.loops.insert(lp.name(), lp);
+    fn index_error(value: &Value) -> String {
+        format!(
+            "Only lists and maps can be indexed, got {}",
+            value.type_name()
+        )
     }
 
-    pub(crate) fn function_exists(&self, scope_depth: u128, name: &String) -> bool {
-        self.functions
+    pub(crate) fn check_key(key: &Value) -> Result<(), String> {
+        match key.is_hashable() {
+            true => Ok(()),
+            false => Err(format!(
+                "Map keys must be numbers, strings, booleans or nil, got {}",
+                key.type_name()
+            )),
+        }
+    }
+
+    fn member_error(&mut self, class: &Class, name: &String) -> InterpretResult {
+        let is_pub = match class.field_visibility(name) {
+            Some(is_pub) => Some(is_pub),
+            None => class.find_method(name).map(|(_, is_pub)| is_pub),
+        };
+
+        let message = match is_pub {
+            Some(_) => format!("{} of {} is private", name, class.name()),
+            None => format!("{} has no member {}", class.name(), name),
+        };
+
+        let error = LoxError::new(message.as_str(), ErrorContext::Runtime, None);
+        match is_pub {
+            Some(_) => self.raise(
+                error.with_help(
+                    format!(
+                        "declare {} with pub to use it outside of {}",
+                        name,
+                        class.name()
+                    )
+                    .as_str(),
+                ),
+            ),
+            None => self.raise(error),
+        }
+    }
+
+    fn undefined_variable(&mut self, name: &String) -> InterpretResult {
+        let message = format!("Undefined variable {}", name);
+        let help = format!("declare {} with let before using it", name);
+        self.raise(LoxError::new(message.as_str(), ErrorContext::Runtime, None).with_help(&help))
+    }
+
+    fn arity_error(&mut self, name: String, arity: u128, args: usize) -> InterpretResult {
+        let message = format!("{} expects {} arguments but got {}", name, arity, args);
+        self.runtime_error(message.as_str())
+    }
+
+    fn runtime_error(&mut self, message: &str) -> InterpretResult {
+        self.raise(LoxError::new(message, ErrorContext::Runtime, None))
+    }
+
+    fn raise(&mut self, error: LoxError) -> InterpretResult {
+        let span = self
+            .frames
+            .last()
+            .and_then(|frame| frame.function.span(frame.ip.saturating_sub(1)));
+
+        let mut entries: Vec<(String, String, usize)> = vec![];
+        for frame in self.frames.iter().rev() {
+            let name = match frame.function.name().as_str() {
+                "##MAIN##" => "script".to_string(),
+                "##FIELDS##" => "field initializers".to_string(),
+                name => format!("{}()", name),
+            };
+            let entry = match frame.function.span(frame.ip.saturating_sub(1)) {
+                Some(span) => format!("[{}] in {}", span, name),
+                None => format!("in {}", name),
+            };
+            match entries.last_mut() {
+                Some((_, last, count)) if *last == entry => *count += 1,
+                _ => entries.push((name, entry, 1)),
+            }
+        }
+
+        let error = error.with_span(span).with_trace(Self::trace(entries));
+        self.report(&error);
+        InterpretResult::RuntimeError
+    }
+
+    fn trace(entries: Vec<(String, String, usize)>) -> Vec<String> {
+        let render = |(name, entry, count): &(String, String, usize)| match count {
+            1 => vec![entry.clone()],
+            _ => vec![
+                entry.clone(),
+                format!("... {} more frames in {}", count - 1, name),
+            ],
+        };
+
+        if entries.len() <= TRACE_EDGE * 2 {
+            return entries.iter().flat_map(render).collect();
+        }
+
+        let skipped: usize = entries[TRACE_EDGE..entries.len() - TRACE_EDGE]
             .iter()
-            .any(|(function, scope)| function.name() == *name && *scope == scope_depth)
+            .map(|(_, _, count)| count)
+            .sum();
+        let mut trace: Vec<String> = entries[..TRACE_EDGE].iter().flat_map(render).collect();
+        trace.push(format!("... {} more frames", skipped));
+        trace.extend(
+            entries[entries.len() - TRACE_EDGE..]
+                .iter()
+                .flat_map(render),
+        );
+        trace
+    }
+
+    pub(crate) fn report(&mut self, error: &LoxError) {
+        let report = match self.plain_diagnostics {
+            true => error.to_string(),
+            false => error.render(&self.file, &self.source),
+        };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.flush();
+        }
+        eprintln!("{}", report);
+        #[cfg(test)]
+        self.stderr.push(report);
+    }
+
+    pub(crate) fn set_file(&mut self, file: &str) {
+        self.file = file.to_string();
+    }
+
+    pub(crate) fn set_plain_diagnostics(&mut self, plain: bool) {
+        self.plain_diagnostics = plain;
+    }
+
+    pub(crate) fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn optimize(&self) -> bool {
+        self.optimize
+    }
+
+    fn make_closure(&mut self, function: Rc<Function>) -> Result<Rc<Closure>, InterpretResult> {
+        let slot_base = self.frame().slot_base;
+        let mut captures = vec![];
+        for (is_local, index) in function.upvalues() {
+            let capture = match is_local {
+                true if slot_base + index <= self.stack.len() => {
+                    Some(self.capture_upvalue(slot_base + index))
+                }
+                true => None,
+                false => self.frame().closure.get_capture(index),
+            };
+            let Some(capture) = capture else {
+                return Err(self.runtime_error("Invalid captured variable"));
+            };
+            captures.push(capture);
+        }
+
+        Ok(Rc::new(Closure::new(function, captures)))
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+
+        match open {
+            Some(upvalue) => upvalue.clone(),
+            None => {
+                let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+                self.open_upvalues.push(upvalue.clone());
+                upvalue
+            }
+        }
+    }
+
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack.get(slot).cloned().unwrap_or(Value::Nil));
+            false
+        });
+    }
+
+    fn instantiate(&mut self, class: Rc<Class>, args: usize) -> InterpretResult {
+        let instance = Value::Instance(Rc::new(RefCell::new(Instance::new(class.clone()))));
+        let slot_base = self.stack.len() - args;
+        self.stack.insert(slot_base, instance.clone());
+
+        match class.find_method(&"init".to_string()) {
+            Some((init, _)) if init.function().arity() == args as u128 => {
+                self.stack.insert(slot_base, instance.clone());
+                let result = self.call(init, slot_base + 1, FrameKind::Initializer);
+                if result != InterpretResult::Ok {
+                    return result;
+                }
+            }
+            Some((init, _)) => {
+                return self.arity_error(class.name(), init.function().arity(), args)
+            }
+            None if args == 0 => (),
+            None => return self.arity_error(class.name(), 0, args),
+        };
+
+        for initializer in class.initializers().into_iter().rev() {
+            self.stack_push(instance.clone());
+            let slot_base = self.stack.len() - 1;
+            let result = self.call(initializer, slot_base, FrameKind::Initializer);
+            if result != InterpretResult::Ok {
+                return result;
+            }
+        }
+
+        InterpretResult::Ok
     }
 
     pub(crate) fn stack_push(&mut self, value: Value) {
-        self.stack.last_mut().unwrap().push(value);
+        self.stack.push(value);
     }
 
     pub(crate) fn stack_pop(&mut self) -> Option<Value> {
-        self.stack.last_mut().unwrap().pop()
+        self.stack.pop()
     }
 
     pub(crate) fn stack_peek(&self) -> Option<Value> {
-        self.stack.last().unwrap().last().cloned()
+        self.stack.last().cloned()
     }
 
-    pub(crate) fn stack_get(&self, address: usize) -> Option<Value> {
-        self.stack.last().unwrap().get(address).cloned()
+    fn stack_peek_at(&self, distance: usize) -> Option<Value> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .and_then(|index| self.stack.get(index))
+            .cloned()
     }
 
-    pub(crate) fn stack_insert(&mut self, address: usize, value: Value) {
-        let frame = self.stack.last_mut().unwrap();
-        frame.remove(address);
-        frame.insert(address, value);
+    fn stack_get(&self, address: usize) -> Option<Value> {
+        self.stack.get(self.frame().slot_base + address).cloned()
     }
 
-    pub(crate) fn start_time(&self) -> Instant {
-        self.start_time
+    fn stack_set(&mut self, address: usize, value: Value) -> bool {
+        let slot_base = self.frame().slot_base;
+        match self.stack.get_mut(slot_base + address) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
     }
 
-    pub(crate) fn resolve_function(
-        &self,
-        name: &String,
-        given_scope: u128,
-    ) -> Option<(Function, usize)> {
-        self.functions
-            .iter()
-            .enumerate()
-            .rev()
-            .find(|(_, (function, scope))| (function.name() == *name) && *scope <= given_scope)
-            .map(|(address, (function, _))| (function.clone(), address))
+    pub(crate) fn start_time(&self) -> Instant {
+        self.start_time
     }
 
     #[cfg(test)]
@@ -571,26 +1108,19 @@ impl VM {
     }
 
     fn get_constant(&self, address: usize) -> Option<&Value> {
-        self.constants.get(address)
-    }
-
-    fn get_loop(&self, name: &String) -> Option<Function> {
-        self.loops.get(name).cloned()
-    }
-
-    fn remove_loop(&mut self, name: &String) {
-        self.loops.remove(name);
+        self.frame().function.constant(address)
     }
 
-    fn is_falsey(&self, value: &Value) -> Option<bool> {
+    fn is_falsey(&self, value: &Value) -> bool {
         match value {
-            Value::String(value) if value.is_empty() => Some(true),
-            Value::Number(value) if *value == 0.0 => Some(true),
-            Value::Boolean(value) => Some(!value),
-            Value::Number(_) => Some(false),
-            Value::String(_) => Some(false),
-            Value::Nil => Some(true),
-            _ => None,
+            Value::String(value) => value.is_empty(),
+            Value::Int(value) => *value == 0,
+            Value::Number(value) => *value == 0.0,
+            Value::Boolean(value) => !value,
+            Value::List(list) => list.borrow().is_empty(),
+            Value::Map(map) => map.borrow().is_empty(),
+            Value::Nil => true,
+            _ => false,
         }
     }
 }